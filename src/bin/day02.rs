@@ -1,7 +1,8 @@
 use std::io;
 use std::io::BufRead;
 
-use clap::Parser;
+use aoc2022::rng::Rng;
+use clap::{Parser, ValueEnum};
 
 /// Argument template for Advent of Code
 #[derive(Parser, Debug)]
@@ -10,9 +11,25 @@ struct Args {
     /// Specify part to compute (1 or 2)
     #[arg(short, long, default_value_t = 1)]
     part: u8,
+
+    /// Report the best achievable score and the per-round regret of the guide
+    #[arg(long)]
+    analyze: bool,
+
+    /// Play the guide against simulated opponents instead of the listed moves
+    #[arg(long, value_enum)]
+    simulate: Option<Opponent>,
+
+    /// Number of simulated tournaments
+    #[arg(long, default_value_t = 1000)]
+    trials: usize,
+
+    /// Seed for the simulation's random number generator
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum RPSMove {
     Paper,
     Rock,
@@ -20,6 +37,28 @@ enum RPSMove {
 }
 use crate::RPSMove::*;
 
+impl RPSMove {
+    const ALL: [RPSMove; 3] = [Rock, Paper, Scissors];
+
+    /// The move that beats this one.
+    fn beaten_by(self) -> RPSMove {
+        match self {
+            Rock => Paper,
+            Paper => Scissors,
+            Scissors => Rock,
+        }
+    }
+
+    /// The move that this one beats.
+    fn beats(self) -> RPSMove {
+        match self {
+            Rock => Scissors,
+            Paper => Rock,
+            Scissors => Paper,
+        }
+    }
+}
+
 fn calculate_score(them: RPSMove, you: RPSMove) -> u32 {
     let outcome_score = match (them, you) {
         (Paper, Paper) => 3,
@@ -40,82 +79,377 @@ fn calculate_score(them: RPSMove, you: RPSMove) -> u32 {
     outcome_score + select_score
 }
 
-fn part1() {
-    let mut result: u32 = 0;
+/// One line of the strategy guide: the opponent's move and the raw second column.
+#[derive(Debug, Clone, Copy)]
+struct Round {
+    them: RPSMove,
+    column: u8,
+}
 
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        let bytes = line.unwrap().as_bytes().to_owned();
-        let (them_b, you_b) = (bytes[0], bytes[2]);
-        let them = match them_b {
-            b'A' => Rock,
-            b'B' => Paper,
-            b'C' => Scissors,
-            _ => panic!("input {:?} not recognized", bytes),
-        };
-        let you = match you_b {
-            b'X' => Rock,
-            b'Y' => Paper,
-            b'Z' => Scissors,
-            _ => panic!("input {:?} not recognized", bytes),
-        };
+fn read_guide() -> Vec<Round> {
+    io::stdin()
+        .lock()
+        .lines()
+        .map(|line| {
+            let bytes = line.unwrap().as_bytes().to_owned();
+            let them = match bytes[0] {
+                b'A' => Rock,
+                b'B' => Paper,
+                b'C' => Scissors,
+                _ => panic!("input {:?} not recognized", bytes),
+            };
+            let column = match bytes[2] {
+                b'X' | b'Y' | b'Z' => bytes[2],
+                _ => panic!("input {:?} not recognized", bytes),
+            };
+            Round { them, column }
+        })
+        .collect()
+}
+
+/// Part 1 reads the second column as the move to play.
+fn decode_move(round: Round) -> RPSMove {
+    match round.column {
+        b'X' => Rock,
+        b'Y' => Paper,
+        b'Z' => Scissors,
+        _ => panic!("column {:?} not recognized", round.column as char),
+    }
+}
+
+/// Part 2 reads the second column as the outcome to aim for.
+fn decode_outcome(round: Round) -> RPSMove {
+    match round.column {
+        b'X' => round.them.beats(),
+        b'Y' => round.them,
+        b'Z' => round.them.beaten_by(),
+        _ => panic!("column {:?} not recognized", round.column as char),
+    }
+}
+
+fn decoder(part: u8) -> fn(Round) -> RPSMove {
+    match part {
+        1 => decode_move,
+        2 => decode_outcome,
+        _ => panic!("part argument not recognized"),
+    }
+}
+
+fn play_guide(guide: &[Round], decode: fn(Round) -> RPSMove) -> u32 {
+    let mut result: u32 = 0;
+    for &round in guide {
+        let you = decode(round);
         println!(
             "calculate_score({:?},{:?}) = {}",
-            them,
+            round.them,
             you,
-            calculate_score(them, you)
+            calculate_score(round.them, you)
         );
 
-        result += calculate_score(them, you);
+        result += calculate_score(round.them, you);
     }
+    result
+}
 
+fn part1(guide: &[Round]) {
+    let result = play_guide(guide, decode_move);
     println!("result = {}", result);
 }
 
-fn part2() {
-    let mut result: u32 = 0;
+fn part2(guide: &[Round]) {
+    let result = play_guide(guide, decode_outcome);
+    println!("result = {}", result);
+}
 
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        let bytes = line.unwrap().as_bytes().to_owned();
-        let (them_b, you_b) = (bytes[0], bytes[2]);
-        let them = match them_b {
-            b'A' => Rock,
-            b'B' => Paper,
-            b'C' => Scissors,
-            _ => panic!("input {:?} not recognized", bytes),
-        };
-        let you = match (them, you_b) {
-            (_, b'Y') => them,
-            (Rock, b'X') => Scissors,
-            (Paper, b'X') => Rock,
-            (Scissors, b'X') => Paper,
-            (Rock, b'Z') => Paper,
-            (Paper, b'Z') => Scissors,
-            (Scissors, b'Z') => Rock,
-
-            _ => panic!("input {:?} not recognized", bytes),
-        };
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RoundAnalysis {
+    them: RPSMove,
+    you: RPSMove,
+    score: u32,
+    best: RPSMove,
+    best_score: u32,
+}
+
+impl RoundAnalysis {
+    fn regret(&self) -> u32 {
+        self.best_score - self.score
+    }
+}
+
+fn analyze_round(round: Round, decode: fn(Round) -> RPSMove) -> RoundAnalysis {
+    let you = decode(round);
+    let best = round.them.beaten_by();
+    RoundAnalysis {
+        them: round.them,
+        you,
+        score: calculate_score(round.them, you),
+        best,
+        best_score: calculate_score(round.them, best),
+    }
+}
+
+fn analyze(guide: &[Round], decode: fn(Round) -> RPSMove) {
+    let rounds = guide
+        .iter()
+        .map(|&round| analyze_round(round, decode))
+        .collect::<Vec<_>>();
+
+    for (i, r) in rounds.iter().enumerate() {
         println!(
-            "calculate_score({:?},{:?}) = {}",
-            them,
-            you,
-            calculate_score(them, you)
+            "round {}: them = {:?}, guide = {:?} ({}), best = {:?} ({}), regret = {}",
+            i + 1,
+            r.them,
+            r.you,
+            r.score,
+            r.best,
+            r.best_score,
+            r.regret()
         );
+    }
+
+    let guide_score = rounds.iter().map(|r| r.score).sum::<u32>();
+    let max_score = rounds.iter().map(|r| r.best_score).sum::<u32>();
+    println!("guide score = {}", guide_score);
+    println!("maximum score = {}", max_score);
+    println!("total regret = {}", max_score - guide_score);
+}
+
+/// How simulated opponents choose their moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Opponent {
+    /// Each move with equal probability
+    Random,
+    /// Moves drawn with the frequencies of the guide's first column
+    Frequency,
+}
 
-        result += calculate_score(them, you);
+/// The sorted scores of simulated tournaments.
+#[derive(Debug, Clone, PartialEq)]
+struct Simulation {
+    scores: Vec<u32>,
+}
+
+impl Simulation {
+    fn min(&self) -> Option<u32> {
+        self.scores.first().copied()
     }
 
-    println!("result = {}", result);
+    fn max(&self) -> Option<u32> {
+        self.scores.last().copied()
+    }
+
+    fn mean(&self) -> f64 {
+        self.scores.iter().map(|&s| s as f64).sum::<f64>() / self.scores.len() as f64
+    }
+
+    fn stddev(&self) -> f64 {
+        let mean = self.mean();
+        let variance = self
+            .scores
+            .iter()
+            .map(|&s| (s as f64 - mean).powi(2))
+            .sum::<f64>()
+            / self.scores.len() as f64;
+        variance.sqrt()
+    }
+
+    /// The score below which `p` percent of the tournaments fall.
+    fn percentile(&self, p: usize) -> u32 {
+        self.scores[(self.scores.len() - 1) * p / 100]
+    }
+
+    /// Splits the scores into `buckets` ranges of equal width, returning each range's lowest
+    /// score and how many tournaments fall into it, along with the width.
+    fn histogram(&self, buckets: u32) -> (u32, Vec<(u32, usize)>) {
+        let (Some(min), Some(max)) = (self.min(), self.max()) else {
+            return (1, vec![]);
+        };
+        let width = ((max - min) / buckets + 1).max(1);
+        let mut counts = vec![0usize; buckets as usize];
+        for &s in &self.scores {
+            counts[((s - min) / width) as usize] += 1;
+        }
+        let ranges = counts
+            .into_iter()
+            .enumerate()
+            .map(|(i, count)| (min + i as u32 * width, count))
+            .collect();
+        (width, ranges)
+    }
+}
+
+/// Plays the guide's moves in `trials` tournaments against opponents drawing their moves
+/// at random, seeded so that the same arguments always give the same scores.
+fn run_simulation(
+    guide: &[Round],
+    decode: fn(Round) -> RPSMove,
+    opponent: Opponent,
+    trials: usize,
+    seed: u64,
+) -> Simulation {
+    let moves = guide.iter().map(|&round| decode(round)).collect::<Vec<_>>();
+    let weights = match opponent {
+        Opponent::Random => [1, 1, 1],
+        Opponent::Frequency => {
+            RPSMove::ALL.map(|m| guide.iter().filter(|r| r.them == m).count() as u64)
+        }
+    };
+    let total_weight = weights.iter().sum::<u64>().max(1);
+
+    let mut rng = Rng::new(seed);
+    let mut scores = (0..trials)
+        .map(|_| {
+            moves
+                .iter()
+                .map(|&you| {
+                    let mut pick = rng.below(total_weight);
+                    let them = RPSMove::ALL
+                        .into_iter()
+                        .zip(weights)
+                        .find(|&(_, w)| {
+                            let hit = pick < w;
+                            pick = pick.saturating_sub(w);
+                            hit
+                        })
+                        .map_or(Rock, |(m, _)| m);
+                    calculate_score(them, you)
+                })
+                .sum::<u32>()
+        })
+        .collect::<Vec<_>>();
+    scores.sort_unstable();
+    Simulation { scores }
+}
+
+fn simulate(
+    guide: &[Round],
+    decode: fn(Round) -> RPSMove,
+    opponent: Opponent,
+    trials: usize,
+    seed: u64,
+) {
+    let simulation = run_simulation(guide, decode, opponent, trials, seed);
+    let (Some(min), Some(max)) = (simulation.min(), simulation.max()) else {
+        println!("no trials to report");
+        return;
+    };
+
+    println!(
+        "opponent = {:?}, trials = {}, seed = {}",
+        opponent, trials, seed
+    );
+    println!("min = {}, max = {}", min, max);
+    println!(
+        "mean = {:.2}, stddev = {:.2}",
+        simulation.mean(),
+        simulation.stddev()
+    );
+    println!(
+        "p5 = {}, median = {}, p95 = {}",
+        simulation.percentile(5),
+        simulation.percentile(50),
+        simulation.percentile(95)
+    );
+
+    let (width, histogram) = simulation.histogram(10);
+    let tallest = histogram.iter().map(|&(_, c)| c).max().unwrap_or(1).max(1);
+    for (lo, count) in histogram {
+        println!(
+            "{:>7}..{:<7} {:>6} {}",
+            lo,
+            lo + width,
+            count,
+            "#".repeat(count * 50 / tallest)
+        );
+    }
 }
 
 fn main() {
-    let part = Args::parse().part;
-    println!("Parsed part = {}", part);
+    let args = Args::parse();
+    println!("Parsed part = {}", args.part);
 
-    match Args::parse().part {
-        1 => part1(),
-        2 => part2(),
-        _ => panic!("part argument not recognized"),
+    let guide = read_guide();
+    if args.analyze {
+        analyze(&guide, decoder(args.part));
+    } else if let Some(opponent) = args.simulate {
+        simulate(&guide, decoder(args.part), opponent, args.trials, args.seed);
+    } else {
+        match args.part {
+            1 => part1(&guide),
+            2 => part2(&guide),
+            _ => panic!("part argument not recognized"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> Vec<Round> {
+        vec![
+            Round {
+                them: Rock,
+                column: b'Y',
+            },
+            Round {
+                them: Paper,
+                column: b'X',
+            },
+            Round {
+                them: Scissors,
+                column: b'Z',
+            },
+        ]
+    }
+
+    #[test]
+    fn test_guide_scores() {
+        assert_eq!(play_guide(&example(), decode_move), 15);
+        assert_eq!(play_guide(&example(), decode_outcome), 12);
+    }
+
+    #[test]
+    fn test_simulation() {
+        let run = |seed| run_simulation(&example(), decode_move, Opponent::Random, 200, seed);
+        let simulation = run(7);
+        assert_eq!(simulation, run(7));
+        assert_ne!(simulation, run(8));
+        assert!(simulation.scores.windows(2).all(|w| w[0] <= w[1]));
+        assert!(simulation.min() >= Some(3) && simulation.max() <= Some(27));
+
+        let (width, histogram) = simulation.histogram(10);
+        assert_eq!(histogram.iter().map(|&(_, c)| c).sum::<usize>(), 200);
+        assert_eq!(histogram[0].0, simulation.min().unwrap());
+        assert_eq!(histogram.len(), 10);
+        assert!(histogram[9].0 + width > simulation.max().unwrap());
+        assert!(simulation.percentile(5) <= simulation.percentile(50));
+        assert!(simulation.percentile(50) <= simulation.percentile(95));
+        assert_eq!(simulation.percentile(100), simulation.max().unwrap());
+
+        // paper against an opponent who has only ever played rock wins every round
+        let rocks = vec![
+            Round {
+                them: Rock,
+                column: b'Y',
+            };
+            4
+        ];
+        let simulation = run_simulation(&rocks, decode_move, Opponent::Frequency, 100, 3);
+        assert!(simulation.scores.iter().all(|&s| s == 4 * 8));
+        assert_eq!(simulation.stddev(), 0.0);
+    }
+
+    #[test]
+    fn test_regret() {
+        let rounds = example()
+            .into_iter()
+            .map(|round| analyze_round(round, decode_move))
+            .collect::<Vec<_>>();
+        assert_eq!(rounds.iter().map(|r| r.best_score).sum::<u32>(), 24);
+        assert_eq!(
+            rounds.iter().map(|r| r.regret()).collect::<Vec<_>>(),
+            vec![0, 8, 1]
+        );
     }
 }
@@ -1,8 +1,9 @@
-use std::collections::HashSet;
+use std::fmt;
 use std::io;
 use std::io::BufRead;
 
 use clap::Parser;
+use color_eyre::eyre::eyre;
 
 /// Argument template for Advent of Code
 #[derive(Parser, Debug)]
@@ -11,6 +12,18 @@ struct Args {
     /// Specify part to compute (1 or 2)
     #[arg(short, long, default_value_t = 1)]
     part: u8,
+
+    /// Number of equally sized compartments in each rucksack (part 1)
+    #[arg(long, default_value_t = 2)]
+    compartments: usize,
+
+    /// Number of elves sharing a badge (part 2)
+    #[arg(long, default_value_t = 3)]
+    group_size: usize,
+
+    /// Print per-rucksack shared items, the moves to separate them and badge checks
+    #[arg(long)]
+    report: bool,
 }
 
 pub fn score_letter(ch: u8) -> u32 {
@@ -21,74 +34,273 @@ pub fn score_letter(ch: u8) -> u32 {
     }
 }
 
-pub fn get_common_letter(a: &[u8], b: &[u8]) -> u8 {
-    let a_set = a.iter().map(|&x| x).collect::<HashSet<u8>>();
-    let b_set = b.iter().map(|&x| x).collect::<HashSet<u8>>();
-    let mut intersection = a_set.intersection(&b_set);
+pub fn letter_for(priority: u32) -> char {
+    match priority {
+        1..=26 => (b'a' + (priority - 1) as u8) as char,
+        27..=52 => (b'A' + (priority - 27) as u8) as char,
+        _ => panic!("bad priority"),
+    }
+}
 
-    intersection.next().unwrap().clone()
+/// A set of rucksack items, stored as a bitmask where bit `n` is the item with priority `n`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ItemSet(u64);
+
+impl ItemSet {
+    pub fn from_items(items: &[u8]) -> color_eyre::Result<Self> {
+        items.iter().try_fold(ItemSet::default(), |set, &ch| {
+            if !ch.is_ascii_alphabetic() {
+                return Err(eyre!("{:?} is not a valid item", ch as char));
+            }
+            Ok(ItemSet(set.0 | 1 << score_letter(ch)))
+        })
+    }
+
+    pub fn intersection(self, other: ItemSet) -> ItemSet {
+        ItemSet(self.0 & other.0)
+    }
+
+    pub fn union(self, other: ItemSet) -> ItemSet {
+        ItemSet(self.0 | other.0)
+    }
+
+    pub fn len(self) -> usize {
+        self.0.count_ones() as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn priorities(self) -> impl Iterator<Item = u32> {
+        (1..=52).filter(move |p| self.0 & 1 << p != 0)
+    }
+
+    /// The priority of the only item in the set, or an error if there isn't exactly one.
+    pub fn single(self) -> color_eyre::Result<u32> {
+        match self.len() {
+            1 => Ok(self.0.trailing_zeros()),
+            0 => Err(eyre!("no item in common")),
+            _ => Err(eyre!("more than one item in common: {}", self)),
+        }
+    }
+}
+
+impl fmt::Display for ItemSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.priorities()
+            .try_for_each(|p| write!(f, "{}", letter_for(p)))
+    }
+}
+
+/// The items present in every one of `slices`.
+pub fn common_items<T: AsRef<[u8]>>(slices: &[T]) -> color_eyre::Result<ItemSet> {
+    let mut sets = slices
+        .iter()
+        .map(|slice| ItemSet::from_items(slice.as_ref()));
+    let first = sets
+        .next()
+        .ok_or_else(|| eyre!("no item lists to compare"))??;
+    sets.try_fold(first, |common, set| {
+        Ok::<_, color_eyre::Report>(common.intersection(set?))
+    })
+}
+
+/// The priority of the single item present in every one of `slices`.
+pub fn common_priority<T: AsRef<[u8]>>(slices: &[T]) -> color_eyre::Result<u32> {
+    common_items(slices)?.single()
+}
+
+/// The items present in more than one of `slices`.
+pub fn shared_items<T: AsRef<[u8]>>(slices: &[T]) -> color_eyre::Result<ItemSet> {
+    let mut seen = ItemSet::default();
+    let mut shared = ItemSet::default();
+    for slice in slices {
+        let set = ItemSet::from_items(slice.as_ref())?;
+        shared = shared.union(seen.intersection(set));
+        seen = seen.union(set);
+    }
+    Ok(shared)
 }
 
-pub fn get_common_letter3(a: &[u8], b: &[u8], c: &[u8]) -> u8 {
-    let a_set = a.iter().map(|&x| x).collect::<HashSet<u8>>();
-    let b_set = b.iter().map(|&x| x).collect::<HashSet<u8>>();
-    let c_set = c.iter().map(|&x| x).collect::<HashSet<u8>>();
-    let a_and_b_set = a_set
-        .intersection(&b_set)
-        .map(|&x| x)
-        .collect::<HashSet<u8>>();
-    let mut result = a_and_b_set.intersection(&c_set);
+/// Moving `count` copies of `item` from one compartment to another (0-based indices).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Move {
+    pub item: char,
+    pub count: usize,
+    pub from: usize,
+    pub to: usize,
+}
 
-    result.next().unwrap().clone()
+/// The fewest item moves that leave every item in a single compartment.
+///
+/// Each shared item is gathered into the compartment already holding most of its copies,
+/// so compartment sizes are not preserved.
+pub fn plan_moves(parts: &[&[u8]]) -> color_eyre::Result<Vec<Move>> {
+    let mut moves = vec![];
+    for priority in shared_items(parts)?.priorities() {
+        let item = letter_for(priority);
+        let counts = parts
+            .iter()
+            .map(|part| part.iter().filter(|&&ch| ch as char == item).count())
+            .collect::<Vec<_>>();
+        let to = (0..counts.len())
+            .rev()
+            .max_by_key(|&i| counts[i])
+            .unwrap_or_default();
+        moves.extend(
+            counts
+                .iter()
+                .enumerate()
+                .filter(|&(from, &count)| from != to && count > 0)
+                .map(|(from, &count)| Move {
+                    item,
+                    count,
+                    from,
+                    to,
+                }),
+        );
+    }
+    Ok(moves)
 }
 
-fn part1() {
+/// Splits a rucksack into `n` equally sized compartments.
+pub fn compartments(rucksack: &[u8], n: usize) -> color_eyre::Result<Vec<&[u8]>> {
+    if n == 0 || rucksack.is_empty() || !rucksack.len().is_multiple_of(n) {
+        return Err(eyre!(
+            "{} items can't be split into {} equal compartments",
+            rucksack.len(),
+            n
+        ));
+    }
+    Ok(rucksack.chunks(rucksack.len() / n).collect())
+}
+
+fn part1(n_compartments: usize) -> color_eyre::Result<()> {
     let mut result: u32 = 0;
 
     let stdin = io::stdin();
     for line in stdin.lock().lines() {
-        let bytes = line.unwrap().as_bytes().to_owned();
-        let (first_half, second_half) = bytes.split_at(bytes.len() / 2);
-        let common_letter = get_common_letter(&first_half, &second_half);
+        let bytes = line?.into_bytes();
+        let priority = compartments(&bytes, n_compartments)
+            .and_then(|parts| common_priority(&parts))
+            .map_err(|e| eyre!("rucksack {:?}: {}", String::from_utf8_lossy(&bytes), e))?;
 
-        result += score_letter(common_letter);
+        result += priority;
     }
 
     println!("result = {}", result);
+    Ok(())
 }
 
-fn part2() {
+fn part2(group_size: usize) -> color_eyre::Result<()> {
     let mut result: u32 = 0;
 
-    let lines = io::stdin()
-        .lock()
-        .lines()
-        .map(|x| x.unwrap())
-        .collect::<Vec<_>>();
-    let chunks = lines.chunks(3);
-
-    for chunk in chunks {
-        let common_letter = get_common_letter3(
-            chunk[0].as_bytes(),
-            chunk[1].as_bytes(),
-            chunk[2].as_bytes(),
-        );
+    let lines = io::stdin().lock().lines().collect::<Result<Vec<_>, _>>()?;
+    if group_size == 0 {
+        return Err(eyre!("group size must be at least 1"));
+    }
+    let chunks = lines.chunks(group_size);
 
-        result += score_letter(common_letter);
+    for (i, chunk) in chunks.enumerate() {
+        if chunk.len() != group_size {
+            return Err(eyre!(
+                "group {} is incomplete: {} of {} rucksacks",
+                i + 1,
+                chunk.len(),
+                group_size
+            ));
+        }
+        let priority = common_priority(chunk).map_err(|e| eyre!("group {}: {}", i + 1, e))?;
+
+        result += priority;
     }
 
     println!("result = {}", result);
+    Ok(())
+}
+
+fn report(n_compartments: usize, group_size: usize) -> color_eyre::Result<()> {
+    let lines = io::stdin().lock().lines().collect::<Result<Vec<_>, _>>()?;
+
+    let mut total_moves = 0;
+    for (i, line) in lines.iter().enumerate() {
+        let parts = compartments(line.as_bytes(), n_compartments)
+            .map_err(|e| eyre!("rucksack {}: {}", i + 1, e))?;
+        let moves = plan_moves(&parts)?;
+        let lifted = moves.iter().map(|m| m.count).sum::<usize>();
+        println!(
+            "rucksack {}: shared = {}, moves = {}",
+            i + 1,
+            shared_items(&parts)?,
+            lifted
+        );
+        for m in &moves {
+            println!(
+                "  move {} {:?} from compartment {} to compartment {}",
+                m.count,
+                m.item,
+                m.from + 1,
+                m.to + 1
+            );
+        }
+        total_moves += lifted;
+    }
+    println!("total moves = {}", total_moves);
+
+    if group_size == 0 {
+        return Err(eyre!("group size must be at least 1"));
+    }
+    let mut unique_badges = 0;
+    for (i, chunk) in lines.chunks(group_size).enumerate() {
+        let badges = common_items(chunk)?;
+        let status = match badges.len() {
+            1 => {
+                unique_badges += 1;
+                "unique"
+            }
+            0 => "missing",
+            _ => "ambiguous",
+        };
+        let incomplete = if chunk.len() < group_size {
+            " (incomplete group)"
+        } else {
+            ""
+        };
+        println!(
+            "group {}: badges = {:?}, {}{}",
+            i + 1,
+            badges.to_string(),
+            status,
+            incomplete
+        );
+    }
+    println!(
+        "unique badges = {} of {}",
+        unique_badges,
+        lines.len().div_ceil(group_size)
+    );
+
+    Ok(())
 }
 
-fn main() {
-    let part = Args::parse().part;
-    println!("Parsed part = {}", part);
+fn main() -> color_eyre::Result<()> {
+    color_eyre::install()?;
+
+    let args = Args::parse();
+    println!("Parsed part = {}", args.part);
 
-    match Args::parse().part {
-        1 => part1(),
-        2 => part2(),
+    if args.report {
+        return report(args.compartments, args.group_size);
+    }
+
+    match args.part {
+        1 => part1(args.compartments)?,
+        2 => part2(args.group_size)?,
         _ => panic!("part argument not recognized"),
     }
+
+    Ok(())
 }
 
 #[cfg(test)]
@@ -104,4 +316,51 @@ mod tests {
         assert_eq!(score_letter(b'L'), 38);
         assert_eq!(score_letter(b'Z'), 52);
     }
+
+    #[test]
+    fn test_item_set() {
+        let a = ItemSet::from_items(b"vJrwpWtwJgWr").unwrap();
+        let b = ItemSet::from_items(b"hcsFMMfFFhFp").unwrap();
+        assert_eq!(a.intersection(b).single().unwrap(), score_letter(b'p'));
+        assert_eq!(a.union(b).len(), 14);
+        assert_eq!(ItemSet::from_items(b"aZ").unwrap().to_string(), "aZ");
+
+        assert!(common_priority(&["ab", "cd"]).is_err());
+        assert!(common_priority(&["ab", "ab"]).is_err());
+        assert!(ItemSet::from_items(b"a1").is_err());
+    }
+
+    #[test]
+    fn test_compartments() {
+        let parts = compartments(b"abcxaeyza", 3).unwrap();
+        assert_eq!(parts, vec![&b"abc"[..], b"xae", b"yza"]);
+        assert_eq!(common_priority(&parts).unwrap(), score_letter(b'a'));
+        assert!(compartments(b"abcde", 2).is_err());
+        assert!(compartments(b"ab", 0).is_err());
+    }
+
+    #[test]
+    fn test_plan_moves() {
+        let parts = compartments(b"vJrwpWtwJgWrhcsFMMfFFhFp", 2).unwrap();
+        assert_eq!(
+            plan_moves(&parts).unwrap(),
+            vec![Move {
+                item: 'p',
+                count: 1,
+                from: 1,
+                to: 0
+            }]
+        );
+
+        let parts = compartments(b"aabbcab", 7).unwrap();
+        assert_eq!(shared_items(&parts).unwrap().to_string(), "ab");
+        assert_eq!(
+            plan_moves(&parts)
+                .unwrap()
+                .iter()
+                .map(|m| m.count)
+                .sum::<usize>(),
+            4
+        );
+    }
 }
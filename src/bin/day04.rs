@@ -1,4 +1,6 @@
+use aoc2022::interval::{depth_runs, Interval};
 use clap::Parser;
+use color_eyre::eyre::eyre;
 
 /// Argument template for Advent of Code
 #[derive(Parser, Debug)]
@@ -7,76 +9,176 @@ struct Args {
     /// Specify part to compute (1 or 2)
     #[arg(short, long, default_value_t = 1)]
     part: u8,
+
+    /// Report how the whole assignment list covers the sections
+    #[arg(long)]
+    coverage: bool,
+
+    /// Print the containment and overlap queries for every group of assignments
+    #[arg(long)]
+    groups: bool,
+}
+
+fn get_input() -> &'static str {
+    // include_str!("../../data/day04/example.txt")
+    include_str!("../../data/day04/input.txt")
+}
+
+fn parse_range(s: &str) -> color_eyre::Result<Interval<u32>> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| eyre!("range {:?} is missing a '-'", s))?;
+    Interval::new(start.parse()?, end.parse()?)
+        .ok_or_else(|| eyre!("range {:?} ends before it starts", s))
+}
+
+/// Parses a line of any number of comma-separated ranges.
+fn parse_group(line: &str) -> color_eyre::Result<Vec<Interval<u32>>> {
+    if line.is_empty() {
+        return Err(eyre!("empty assignment group"));
+    }
+    line.split(',').map(parse_range).collect()
+}
+
+/// Whether some range fully contains another one of the group.
+fn any_contains(group: &[Interval<u32>]) -> bool {
+    group.iter().enumerate().any(|(i, a)| {
+        group
+            .iter()
+            .enumerate()
+            .any(|(j, b)| i != j && a.contains(b))
+    })
+}
+
+/// The sections shared by every range of the group.
+fn common_section(group: &[Interval<u32>]) -> Option<Interval<u32>> {
+    let (first, rest) = group.split_first()?;
+    rest.iter()
+        .try_fold(*first, |common, range| common.intersection(range))
+}
+
+fn pairwise_overlaps(group: &[Interval<u32>]) -> usize {
+    group
+        .iter()
+        .enumerate()
+        .map(|(i, a)| group[i + 1..].iter().filter(|b| a.overlaps(b)).count())
+        .sum()
 }
 
 fn part1() -> color_eyre::Result<()> {
-    let full_overlaps = include_str!("../../data/day04/input.txt")
-        .lines()
-        .filter_map(|line| {
-            let ranges = line.split_once(',')?;
-            let first = ranges.0.split_once('-')?;
-            let (x1, y1) = (
-                first.0.parse::<u32>().unwrap(),
-                first.1.parse::<u32>().unwrap(),
-            );
-
-            let second = ranges.1.split_once('-')?;
-            let (x2, y2) = (
-                second.0.parse::<u32>().unwrap(),
-                second.1.parse::<u32>().unwrap(),
-            );
-            let is_contained = (x1 <= x2 && y1 >= y2) || (x2 <= x1 && y2 >= y1);
-            dbg!((line, is_contained));
-
-            if is_contained {
-                Some(())
-            } else {
-                None
-            }
-        })
-        .count();
+    let mut full_overlaps = 0;
+    for line in get_input().lines() {
+        let group = parse_group(line)?;
+        let is_contained = any_contains(&group);
+        dbg!((line, is_contained));
+
+        if is_contained {
+            full_overlaps += 1;
+        }
+    }
     dbg!(full_overlaps);
     Ok(())
 }
 
 fn part2() -> color_eyre::Result<()> {
-    let full_overlaps = include_str!("../../data/day04/input.txt")
-        .lines()
-        .filter_map(|line| {
-            let ranges = line.split_once(',')?;
-            let first = ranges.0.split_once('-')?;
-            let (x1, y1) = (
-                first.0.parse::<u32>().unwrap(),
-                first.1.parse::<u32>().unwrap(),
-            );
-
-            let second = ranges.1.split_once('-')?;
-            let (x2, y2) = (
-                second.0.parse::<u32>().unwrap(),
-                second.1.parse::<u32>().unwrap(),
-            );
-            let is_overlapping = (x1 <= x2 && y1 >= x2)
-                || (x1 <= y2 && y1 >= y2)
-                || (x2 <= x1 && y2 >= x1)
-                || (x2 <= y1 && y2 >= y1);
-            dbg!((line, is_overlapping));
-
-            if is_overlapping {
-                Some(())
-            } else {
-                None
-            }
-        })
-        .count();
-    dbg!(full_overlaps);
+    let mut overlaps = 0;
+    for line in get_input().lines() {
+        let group = parse_group(line)?;
+        let is_overlapping = common_section(&group).is_some();
+        dbg!((line, is_overlapping));
+
+        if is_overlapping {
+            overlaps += 1;
+        }
+    }
+    dbg!(overlaps);
+    Ok(())
+}
+
+fn print_groups() -> color_eyre::Result<()> {
+    for (i, line) in get_input().lines().enumerate() {
+        let group = parse_group(line)?;
+        let common = common_section(&group).map_or_else(|| "none".to_string(), |c| c.to_string());
+        println!(
+            "group {} ({} ranges): contains = {}, common = {}, overlapping pairs = {}",
+            i + 1,
+            group.len(),
+            any_contains(&group),
+            common,
+            pairwise_overlaps(&group)
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Coverage {
+    covered: u64,
+    covered_more_than_once: u64,
+    max_depth: usize,
+    most_overlapped: Option<Interval<u32>>,
+    uncovered: Vec<Interval<u32>>,
+}
+
+fn coverage(ranges: impl IntoIterator<Item = Interval<u32>>) -> Coverage {
+    let mut report = Coverage::default();
+    for (run, depth) in depth_runs(ranges) {
+        match depth {
+            0 => report.uncovered.push(run),
+            _ => report.covered += run.len(),
+        }
+        if depth > 1 {
+            report.covered_more_than_once += run.len();
+        }
+        if depth > report.max_depth {
+            report.max_depth = depth;
+            report.most_overlapped = Some(run);
+        }
+    }
+    report
+}
+
+fn print_coverage() -> color_eyre::Result<()> {
+    let mut ranges = vec![];
+    for line in get_input().lines() {
+        ranges.extend(parse_group(line)?);
+    }
+    let report = coverage(ranges);
+
+    println!("sections covered: {}", report.covered);
+    println!(
+        "sections covered by more than one elf: {}",
+        report.covered_more_than_once
+    );
+    if let Some(run) = report.most_overlapped {
+        println!(
+            "most overlapped sections: {} ({} elves)",
+            run, report.max_depth
+        );
+    }
+    println!(
+        "sections nobody covers: {} in {} gaps",
+        report.uncovered.iter().map(|run| run.len()).sum::<u64>(),
+        report.uncovered.len()
+    );
+    for run in &report.uncovered {
+        println!("  {}", run);
+    }
     Ok(())
 }
 
 fn main() -> color_eyre::Result<()> {
-    let part = Args::parse().part;
-    println!("Parsed part = {}", part);
+    let args = Args::parse();
+    println!("Parsed part = {}", args.part);
 
-    match Args::parse().part {
+    if args.coverage {
+        return print_coverage();
+    }
+    if args.groups {
+        return print_groups();
+    }
+
+    match args.part {
         1 => part1()?,
         2 => part2()?,
         _ => panic!("part argument not recognized"),
@@ -84,3 +186,38 @@ fn main() -> color_eyre::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        let ranges = include_str!("../../data/day04/example.txt")
+            .lines()
+            .flat_map(|line| parse_group(line).unwrap());
+        let report = coverage(ranges);
+        assert_eq!(report.covered, 8);
+        assert_eq!(report.covered_more_than_once, 7);
+        assert_eq!(report.max_depth, 8);
+        assert_eq!(report.most_overlapped, Interval::new(6, 6));
+        assert!(report.uncovered.is_empty());
+    }
+
+    #[test]
+    fn test_group_queries() {
+        let group = parse_group("2-8,3-7,5-9").unwrap();
+        assert!(any_contains(&group));
+        assert_eq!(common_section(&group), Interval::new(5, 7));
+        assert_eq!(pairwise_overlaps(&group), 3);
+
+        let group = parse_group("1-2,2-3,3-4,6-6").unwrap();
+        assert!(!any_contains(&group));
+        assert_eq!(common_section(&group), None);
+        assert_eq!(pairwise_overlaps(&group), 2);
+
+        assert!(any_contains(&parse_group("1-2,1-2").unwrap()));
+        assert!(parse_group("").is_err());
+        assert!(parse_group("1-2,").is_err());
+    }
+}
@@ -1,4 +1,9 @@
-use clap::Parser;
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Parser, ValueEnum};
+use color_eyre::eyre::eyre;
 
 /// Argument template for Advent of Code
 #[derive(Parser, Debug)]
@@ -7,6 +12,42 @@ struct Args {
     /// Specify part to compute (1 or 2)
     #[arg(short, long, default_value_t = 1)]
     part: u8,
+
+    /// Crane model to run the moves with (defaults to 9000 for part 1 and 9001 for part 2)
+    #[arg(long, value_enum)]
+    crane: Option<CraneModel>,
+
+    /// Most crates the limited crane can lift at once
+    #[arg(long, default_value_t = 3)]
+    capacity: usize,
+
+    /// Show the stacks after this many moves (0 is the starting drawing)
+    #[arg(long)]
+    step: Option<usize>,
+
+    /// Replay every move in the terminal
+    #[arg(long)]
+    animate: bool,
+
+    /// Delay between animation frames, in milliseconds
+    #[arg(long, default_value_t = 250)]
+    delay: u64,
+
+    /// Print the drawing of the final stacks
+    #[arg(long)]
+    dump: bool,
+
+    /// Recover starting stacks that end with these top crates (comma separated for wide labels)
+    #[arg(long)]
+    target_tops: Option<String>,
+
+    /// Recover the starting stacks from a drawing of the final stacks
+    #[arg(long)]
+    target_file: Option<std::path::PathBuf>,
+
+    /// Look for a shorter move list leading to the same final stacks
+    #[arg(long)]
+    optimize: bool,
 }
 
 fn get_input() -> &'static str {
@@ -16,106 +57,801 @@ fn get_input() -> &'static str {
 
 #[derive(Debug)]
 struct Day05Data {
-    stacks: Vec<Vec<u8>>,
-    moves: Vec<(usize, usize, usize)>,
+    stacks: Vec<Vec<String>>,
+    moves: Vec<Move>,
 }
 
-fn process_input() -> color_eyre::Result<Day05Data> {
-    let (stack_state, moves_lines) = get_input().split_once("\n\n").unwrap();
+/// Moving `count` crates from stack `from` to stack `to`, as 1-based stack numbers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Move {
+    count: usize,
+    from: usize,
+    to: usize,
+}
 
-    let mut stacks = stack_state
-        .lines()
-        .last()
-        .unwrap()
-        .split_whitespace()
-        .map(|_| vec![])
-        .collect::<Vec<Vec<u8>>>();
-    stack_state.lines().rev().skip(1).for_each(|line| {
-        let bytes = line.as_bytes();
-        for i in 0..stacks.len() {
-            let ch = bytes[1 + (4 * i)];
-            if ch.is_ascii_alphabetic() {
-                stacks.get_mut(i).unwrap().push(ch);
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "move {} from {} to {}", self.count, self.from, self.to)
+    }
+}
+
+fn parse_move(line: &str) -> Option<Move> {
+    // ex: move 5 from 8 to 3
+    match line.split_whitespace().collect::<Vec<&str>>()[..] {
+        ["move", count, "from", from, "to", to] => Some(Move {
+            count: count.parse().ok()?,
+            from: from.parse().ok()?,
+            to: to.parse().ok()?,
+        }),
+        _ => None,
+    }
+}
+
+/// A word of the drawing along with the byte columns `start..end` it spans.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Token<'a> {
+    text: &'a str,
+    start: usize,
+    end: usize,
+}
+
+impl Token<'_> {
+    fn overlaps(&self, other: &Token) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+/// Splits the stack labels row (` 1   2   3 `) into its labels.
+fn label_tokens(line: &str) -> Vec<Token<'_>> {
+    let mut tokens = vec![];
+    let mut start = None;
+    for (i, ch) in line.char_indices().chain([(line.len(), ' ')]) {
+        match (start, ch.is_whitespace()) {
+            (None, false) => start = Some(i),
+            (Some(s), true) => {
+                tokens.push(Token {
+                    text: &line[s..i],
+                    start: s,
+                    end: i,
+                });
+                start = None;
             }
+            _ => {}
         }
-    });
+    }
+    tokens
+}
+
+/// Splits a row of crates (`[Z] [M] [P]`) into crates, labels may be several characters wide.
+fn crate_tokens(line: &str) -> color_eyre::Result<Vec<Token<'_>>> {
+    let mut tokens = vec![];
+    let mut rest = line;
+    let mut offset = 0;
+    loop {
+        let trimmed = rest.trim_start();
+        if trimmed.is_empty() {
+            return Ok(tokens);
+        }
+        let start = offset + rest.len() - trimmed.len();
+        let label = trimmed
+            .strip_prefix('[')
+            .and_then(|t| t.split_once(']'))
+            .map(|(label, _)| label)
+            .filter(|label| !label.is_empty() && !label.contains(char::is_whitespace))
+            .ok_or_else(|| eyre!("expected a crate like [A] at column {}", start + 1))?;
+        let end = start + label.len() + 2;
+        tokens.push(Token {
+            text: label,
+            start,
+            end,
+        });
+        rest = &line[end..];
+        offset = end;
+    }
+}
+
+/// Reads the stack drawing, bottom row last, into stacks of crates (bottom first).
+fn parse_stacks(drawing: &str) -> color_eyre::Result<Vec<Vec<String>>> {
+    let lines = drawing.lines().collect::<Vec<_>>();
+    let (label_line, crate_lines) = lines
+        .split_last()
+        .ok_or_else(|| eyre!("the stack drawing is empty"))?;
+
+    let labels = label_tokens(label_line);
+    for (i, label) in labels.iter().enumerate() {
+        if label.text.parse::<usize>().ok() != Some(i + 1) {
+            return Err(eyre!(
+                "line {}: expected stack label {} but found {:?}",
+                lines.len(),
+                i + 1,
+                label.text
+            ));
+        }
+    }
+
+    let mut stacks = vec![vec![]; labels.len()];
+    for (line_no, line) in crate_lines.iter().enumerate().rev() {
+        let line_no = line_no + 1;
+        for token in crate_tokens(line).map_err(|e| eyre!("line {}: {}", line_no, e))? {
+            let mut matching = labels
+                .iter()
+                .enumerate()
+                .filter(|(_, label)| token.overlaps(label));
+            let i = match (matching.next(), matching.next()) {
+                (Some((i, _)), None) => i,
+                _ => {
+                    return Err(eyre!(
+                    "line {}: crate [{}] at column {} doesn't line up with a single stack label",
+                    line_no,
+                    token.text,
+                    token.start + 1
+                ))
+                }
+            };
+            stacks[i].push(token.text.to_string());
+        }
+    }
+    Ok(stacks)
+}
+
+fn process_input() -> color_eyre::Result<Day05Data> {
+    let (stack_state, moves_lines) = get_input()
+        .split_once("\n\n")
+        .ok_or_else(|| eyre!("expected a blank line between the stacks and the moves"))?;
+
+    let stacks = parse_stacks(stack_state)?;
 
     let moves = moves_lines
         .lines()
-        .filter_map(|line| {
-            let words = line.split_whitespace().collect::<Vec<&str>>();
-            // ex: move 5 from 8 to 3
-            Some((
-                words.get(1)?.parse().ok()?,
-                words.get(3)?.parse().ok()?,
-                words.get(5)?.parse().ok()?,
-            ))
+        .enumerate()
+        .map(|(i, line)| {
+            parse_move(line).ok_or_else(|| {
+                eyre!(
+                    "line {}: expected a move like \"move 1 from 2 to 3\", found {:?}",
+                    stack_state.lines().count() + 2 + i,
+                    line
+                )
+            })
         })
-        .collect::<Vec<(usize, usize, usize)>>();
+        .collect::<color_eyre::Result<Vec<_>>>()?;
     Ok(Day05Data { stacks, moves })
 }
 
-fn process_move(
-    stacks: &mut Vec<Vec<u8>>,
-    (n, from, to): (usize, usize, usize),
+/// A crane model, deciding how many crates go up in each lift.
+trait Crane {
+    /// Sizes of the lifts needed to move `count` crates, first lift first.
+    fn lifts(&self, count: usize) -> color_eyre::Result<Vec<usize>>;
+
+    /// Runs a single move, failing without touching the stacks if it isn't possible.
+    fn apply(&self, stacks: &mut [Vec<String>], mv: Move) -> color_eyre::Result<()> {
+        transfer(stacks, mv, &self.lifts(mv.count)?)
+    }
+
+    /// Reverts a move previously done by `apply`, running its lifts backwards.
+    fn undo(&self, stacks: &mut [Vec<String>], mv: Move) -> color_eyre::Result<()> {
+        let mut lifts = self.lifts(mv.count)?;
+        lifts.reverse();
+        let back = Move {
+            from: mv.to,
+            to: mv.from,
+            ..mv
+        };
+        transfer(stacks, back, &lifts)
+    }
+}
+
+/// Lifts crates one at a time, reversing their order.
+struct CrateMover9000;
+
+/// Lifts all the crates of a move at once, keeping their order.
+struct CrateMover9001;
+
+/// Lifts at most `capacity` crates at once, keeping their order within each lift.
+struct LimitedCrane {
+    capacity: usize,
+}
+
+impl Crane for CrateMover9000 {
+    fn lifts(&self, count: usize) -> color_eyre::Result<Vec<usize>> {
+        Ok(vec![1; count])
+    }
+}
+
+impl Crane for CrateMover9001 {
+    fn lifts(&self, count: usize) -> color_eyre::Result<Vec<usize>> {
+        Ok(if count == 0 { vec![] } else { vec![count] })
+    }
+}
+
+impl Crane for LimitedCrane {
+    fn lifts(&self, count: usize) -> color_eyre::Result<Vec<usize>> {
+        if self.capacity == 0 {
+            return Err(eyre!("a crane with no capacity can't lift anything"));
+        }
+        let mut lifts = vec![self.capacity; count / self.capacity];
+        if !count.is_multiple_of(self.capacity) {
+            lifts.push(count % self.capacity);
+        }
+        Ok(lifts)
+    }
+}
+
+/// Moves the crates in lifts of the given sizes, each lift taking from the top of the stack.
+fn transfer(stacks: &mut [Vec<String>], mv: Move, lifts: &[usize]) -> color_eyre::Result<()> {
+    let n_stacks = stacks.len();
+    let stack_index = |number: usize| {
+        number
+            .checked_sub(1)
+            .filter(|&i| i < n_stacks)
+            .ok_or_else(|| {
+                eyre!(
+                    "there is no stack {} (stacks are 1 to {})",
+                    number,
+                    n_stacks
+                )
+            })
+    };
+    let (from, to) = (stack_index(mv.from)?, stack_index(mv.to)?);
+
+    let available = stacks[from].len();
+    if available < mv.count {
+        return Err(eyre!(
+            "can't take {} crates from stack {} holding {}",
+            mv.count,
+            mv.from,
+            available
+        ));
+    }
+    // putting crates back where they were lifted from leaves the stack as it was
+    if from == to {
+        return Ok(());
+    }
+
+    let mut lifted = stacks[from].split_off(available - mv.count);
+    for &size in lifts {
+        let start = lifted.len() - size;
+        stacks[to].extend(lifted.drain(start..));
+    }
+    Ok(())
+}
+
+fn top_crates(stacks: &[Vec<String>]) -> String {
+    stacks
+        .iter()
+        .filter_map(|stack| stack.last().map(String::as_str))
+        .collect()
+}
+
+/// Draws the stacks the way the puzzle input does, labels row included.
+fn render(stacks: &[Vec<String>]) -> String {
+    let width = stacks
+        .iter()
+        .flatten()
+        .map(|label| label.len() + 2)
+        .chain([3, stacks.len().to_string().len()])
+        .max()
+        .unwrap_or(3);
+    let height = stacks.iter().map(Vec::len).max().unwrap_or(0);
+
+    let mut lines = (0..height)
+        .rev()
+        .map(|level| {
+            stacks
+                .iter()
+                .map(|stack| match stack.get(level) {
+                    Some(label) => format!("{:<width$}", format!("[{}]", label)),
+                    None => " ".repeat(width),
+                })
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>();
+    lines.push(
+        (1..=stacks.len())
+            .map(|number| format!("{:^width$}", number))
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+    lines.join("\n")
+}
+
+/// Crane models selectable from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CraneModel {
+    /// CrateMover 9000, one crate per lift
+    #[value(name = "9000")]
+    Mover9000,
+    /// CrateMover 9001, the whole move in one lift
+    #[value(name = "9001")]
+    Mover9001,
+    /// At most `--capacity` crates per lift
+    Limited,
+}
+
+impl CraneModel {
+    fn build(self, capacity: usize) -> Box<dyn Crane> {
+        match self {
+            CraneModel::Mover9000 => Box::new(CrateMover9000),
+            CraneModel::Mover9001 => Box::new(CrateMover9001),
+            CraneModel::Limited => Box::new(LimitedCrane { capacity }),
+        }
+    }
+}
+
+fn apply_all(
+    crane: &dyn Crane,
+    stacks: &mut [Vec<String>],
+    moves: &[Move],
 ) -> color_eyre::Result<()> {
-    for _ in 0..n {
-        let item = stacks.get_mut(from - 1).unwrap().pop().unwrap();
-        stacks.get_mut(to - 1).unwrap().push(item);
+    for (i, mv) in moves.iter().enumerate() {
+        crane
+            .apply(stacks, *mv)
+            .map_err(|e| eyre!("move {} ({}): {}", i + 1, mv, e))?;
     }
     Ok(())
 }
 
-fn process_move_bulk(
-    stacks: &mut Vec<Vec<u8>>,
-    (n, from, to): (usize, usize, usize),
+fn undo_all(
+    crane: &dyn Crane,
+    stacks: &mut [Vec<String>],
+    moves: &[Move],
 ) -> color_eyre::Result<()> {
-    let mut from_stack = stacks.get_mut(from - 1).unwrap();
-    let mut items = from_stack.split_off(from_stack.len() - n);
-    let mut to_stack = stacks.get_mut(to - 1).unwrap();
-    to_stack.append(&mut items);
+    for (i, mv) in moves.iter().enumerate().rev() {
+        crane
+            .undo(stacks, *mv)
+            .map_err(|e| eyre!("undoing move {} ({}): {}", i + 1, mv, e))?;
+    }
     Ok(())
 }
 
-fn part1() -> color_eyre::Result<()> {
+/// Runs every move, calling `visit` with the stacks before the first move and after each one.
+fn run_moves(
+    crane: &dyn Crane,
+    mut visit: impl FnMut(usize, Option<&Move>, &[Vec<String>]),
+) -> color_eyre::Result<Vec<Vec<String>>> {
     let mut data = process_input()?;
-    for move_tuple in data.moves {
-        process_move(&mut data.stacks, move_tuple)?;
+    visit(0, None, &data.stacks);
+    for (i, mv) in data.moves.iter().enumerate() {
+        crane
+            .apply(&mut data.stacks, *mv)
+            .map_err(|e| eyre!("move {} ({}): {}", i + 1, mv, e))?;
+        visit(i + 1, Some(mv), &data.stacks);
     }
-    println!(
-        "Part 1 answer: {:?}",
-        data.stacks
-            .iter()
-            .filter_map(|stack| stack.last().map(|ch| *ch as char))
-            .collect::<String>()
-    );
+    Ok(data.stacks)
+}
+
+fn part1(crane: &dyn Crane) -> color_eyre::Result<()> {
+    let stacks = run_moves(crane, |_, _, _| {})?;
+    println!("Part 1 answer: {:?}", top_crates(&stacks));
     Ok(())
 }
 
-fn part2() -> color_eyre::Result<()> {
-    let mut data = process_input()?;
-    for move_tuple in data.moves {
-        process_move_bulk(&mut data.stacks, move_tuple)?;
+fn part2(crane: &dyn Crane) -> color_eyre::Result<()> {
+    let stacks = run_moves(crane, |_, _, _| {})?;
+    println!("Part 2 answer: {:?}", top_crates(&stacks));
+    Ok(())
+}
+
+fn print_state(step: usize, mv: Option<&Move>, stacks: &[Vec<String>]) {
+    match mv {
+        Some(mv) => println!("after move {}: {}", step, mv),
+        None => println!("starting stacks"),
+    }
+    println!("{}\n", render(stacks));
+}
+
+fn visualize(crane: &dyn Crane, args: &Args) -> color_eyre::Result<()> {
+    let mut found_step = false;
+    let stacks = run_moves(crane, |i, mv, stacks| {
+        if args.animate {
+            // clear the screen and go back to the top left corner
+            print!("\x1b[2J\x1b[H");
+            print_state(i, mv, stacks);
+            thread::sleep(Duration::from_millis(args.delay));
+        }
+        if args.step == Some(i) {
+            found_step = true;
+            print_state(i, mv, stacks);
+        }
+    })?;
+
+    if let Some(step) = args.step.filter(|_| !found_step) {
+        return Err(eyre!("there is no step {}, the moves stop earlier", step));
+    }
+    if args.dump {
+        println!("final stacks, tops = {:?}", top_crates(&stacks));
+        println!("{}", render(&stacks));
+    }
+    Ok(())
+}
+
+/// Placeholder for crates the target doesn't pin down.
+const UNKNOWN: &str = "?";
+
+/// Final stacks with the requested tops, unknown crates below them.
+///
+/// Stack heights don't depend on the crane, so they come from running the moves forwards.
+fn stacks_with_tops(data: &Day05Data, tops: &str) -> color_eyre::Result<Vec<Vec<String>>> {
+    let mut heights = data.stacks.clone();
+    apply_all(&CrateMover9001, &mut heights, &data.moves)?;
+
+    let tops = if tops.contains(',') {
+        tops.split(',').map(str::to_string).collect::<Vec<_>>()
+    } else {
+        tops.chars().map(String::from).collect()
+    };
+    let non_empty = heights.iter().filter(|stack| !stack.is_empty()).count();
+    if tops.len() != non_empty {
+        return Err(eyre!(
+            "the moves leave {} non-empty stacks but {} tops were given",
+            non_empty,
+            tops.len()
+        ));
+    }
+
+    let mut tops = tops.into_iter();
+    Ok(heights
+        .iter()
+        .map(|stack| {
+            let mut target = vec![UNKNOWN.to_string(); stack.len()];
+            if let (Some(top), Some(wanted)) = (target.last_mut(), stack.last().and(tops.next())) {
+                *top = wanted;
+            }
+            target
+        })
+        .collect())
+}
+
+/// Whether `actual` matches `target`, where unknown crates in the target match anything.
+fn matches_target(actual: &[Vec<String>], target: &[Vec<String>]) -> bool {
+    actual.len() == target.len()
+        && actual.iter().zip(target).all(|(a, t)| {
+            a.len() == t.len() && a.iter().zip(t).all(|(a, t)| t == UNKNOWN || a == t)
+        })
+}
+
+fn inverse(args: &Args) -> color_eyre::Result<()> {
+    let data = process_input()?;
+    let target = match (&args.target_file, &args.target_tops) {
+        (Some(path), _) => parse_stacks(std::fs::read_to_string(path)?.trim_end_matches('\n'))?,
+        (None, Some(tops)) => stacks_with_tops(&data, tops)?,
+        (None, None) => return Err(eyre!("no target to recover the starting stacks from")),
+    };
+
+    let models = match args.crane {
+        Some(model) => vec![model],
+        None => vec![CraneModel::Mover9000, CraneModel::Mover9001],
+    };
+    for model in models {
+        let crane = model.build(args.capacity);
+        let mut start = target.clone();
+        undo_all(crane.as_ref(), &mut start, &data.moves)?;
+
+        let mut replayed = start.clone();
+        apply_all(crane.as_ref(), &mut replayed, &data.moves)?;
+        let round_trip = matches_target(&replayed, &target);
+
+        println!("{:?}: starting stacks", model);
+        println!("{}", render(&start));
+        println!(
+            "round trip {}, tops = {:?}\n",
+            if round_trip { "ok" } else { "FAILED" },
+            top_crates(&replayed)
+        );
+        if !round_trip {
+            return Err(eyre!("{:?} didn't reproduce the target stacks", model));
+        }
+    }
+    Ok(())
+}
+
+/// Combines two consecutive moves into the moves they're equivalent to for `crane`, if possible.
+fn combine(crane: &dyn Crane, first: Move, second: Move) -> color_eyre::Result<Option<Vec<Move>>> {
+    // same stacks: one move works if its lifts are exactly the lifts of both moves in turn
+    if (first.from, first.to) == (second.from, second.to) {
+        let merged = Move {
+            count: first.count + second.count,
+            ..first
+        };
+        let both = [crane.lifts(first.count)?, crane.lifts(second.count)?].concat();
+        return Ok((crane.lifts(merged.count)? == both).then(|| vec![merged]));
+    }
+    // a round trip puts the crates back as they were if undoing the move is the same as
+    // moving them back, which happens when its lifts read the same in both directions
+    if (first.from, first.to, first.count) == (second.to, second.from, second.count) {
+        let lifts = crane.lifts(first.count)?;
+        return Ok(lifts.iter().eq(lifts.iter().rev()).then(Vec::new));
+    }
+    Ok(None)
+}
+
+/// Peephole pass over the moves, dropping no-op moves and combining neighbours until nothing
+/// changes.
+fn optimize_moves(crane: &dyn Crane, moves: &[Move]) -> color_eyre::Result<Vec<Move>> {
+    let mut optimized: Vec<Move> = vec![];
+    for &mv in moves {
+        if mv.count == 0 || mv.from == mv.to {
+            continue;
+        }
+        let mut pending = vec![mv];
+        while let Some(next) = pending.pop() {
+            match optimized.last() {
+                Some(&last) => match combine(crane, last, next)? {
+                    Some(replacement) => {
+                        optimized.pop();
+                        pending.extend(replacement);
+                    }
+                    None => optimized.push(next),
+                },
+                None => optimized.push(next),
+            }
+        }
+    }
+    Ok(optimized)
+}
+
+fn count_lifts(crane: &dyn Crane, moves: &[Move]) -> color_eyre::Result<usize> {
+    moves
+        .iter()
+        .map(|mv| Ok(crane.lifts(mv.count)?.len()))
+        .sum()
+}
+
+fn optimize(crane: &dyn Crane) -> color_eyre::Result<()> {
+    let data = process_input()?;
+    let optimized = optimize_moves(crane, &data.moves)?;
+
+    let mut expected = data.stacks.clone();
+    apply_all(crane, &mut expected, &data.moves)?;
+    let mut actual = data.stacks.clone();
+    apply_all(crane, &mut actual, &optimized)?;
+    if actual != expected {
+        return Err(eyre!(
+            "the optimized moves don't reach the same final stacks"
+        ));
     }
+
+    for mv in &optimized {
+        println!("{}", mv);
+    }
+    println!("moves: {} -> {}", data.moves.len(), optimized.len());
     println!(
-        "Part 2 answer: {:?}",
-        data.stacks
-            .iter()
-            .filter_map(|stack| stack.last().map(|ch| *ch as char))
-            .collect::<String>()
+        "crate lifts: {} -> {}",
+        count_lifts(crane, &data.moves)?,
+        count_lifts(crane, &optimized)?
     );
     Ok(())
 }
 
 fn main() -> color_eyre::Result<()> {
-    let part = Args::parse().part;
-    println!("Parsed part = {}", part);
+    let args = Args::parse();
+    println!("Parsed part = {}", args.part);
+
+    let default_crane = match args.part {
+        1 => CraneModel::Mover9000,
+        2 => CraneModel::Mover9001,
+        _ => panic!("part argument not recognized"),
+    };
+    let crane = args.crane.unwrap_or(default_crane).build(args.capacity);
+
+    if args.optimize {
+        return optimize(crane.as_ref());
+    }
+    if args.target_tops.is_some() || args.target_file.is_some() {
+        return inverse(&args);
+    }
+    if args.animate || args.step.is_some() || args.dump {
+        return visualize(crane.as_ref(), &args);
+    }
 
-    match Args::parse().part {
-        1 => part1()?,
-        2 => part2()?,
+    match args.part {
+        1 => part1(crane.as_ref())?,
+        2 => part2(crane.as_ref())?,
         _ => panic!("part argument not recognized"),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_stacks() {
+        let stacks = parse_stacks(
+            include_str!("../../data/day05/example.txt")
+                .split_once("\n\n")
+                .unwrap()
+                .0,
+        )
+        .unwrap();
+        assert_eq!(stacks, vec![vec!["Z", "N"], vec!["M", "C", "D"], vec!["P"]]);
+
+        // trailing spaces trimmed, ten stacks and wider crate labels
+        let drawing = [
+            "                                    [K]",
+            "[A]                     [GG]        [J]",
+            "[B] [C] [D] [E] [F] [G] [HH] [I]    [L]",
+            " 1   2   3   4   5   6   7    8   9  10",
+        ]
+        .join("\n");
+        let stacks = parse_stacks(&drawing).unwrap();
+        assert_eq!(stacks.len(), 10);
+        assert_eq!(stacks[0], vec!["B", "A"]);
+        assert_eq!(stacks[6], vec!["HH", "GG"]);
+        assert!(stacks[8].is_empty());
+        assert_eq!(stacks[9], vec!["L", "J", "K"]);
+
+        assert!(parse_stacks("[A] [B]\n 1   3 ").is_err());
+        assert!(parse_stacks("[A]     [B]\n 1   2 ").is_err());
+        assert!(parse_stacks("[A] B\n 1   2 ").is_err());
+    }
+
+    #[test]
+    fn test_cranes() {
+        let stacks = || {
+            vec![
+                vec!["A", "B", "C", "D"]
+                    .into_iter()
+                    .map(String::from)
+                    .collect(),
+                vec![],
+            ]
+        };
+        let mv = Move {
+            count: 4,
+            from: 1,
+            to: 2,
+        };
+        let run = |crane: &dyn Crane| {
+            let mut stacks = stacks();
+            crane.apply(&mut stacks, mv).unwrap();
+            stacks[1].concat()
+        };
+        assert_eq!(run(&CrateMover9000), "DCBA");
+        assert_eq!(run(&CrateMover9001), "ABCD");
+        assert_eq!(run(&LimitedCrane { capacity: 3 }), "BCDA");
+        assert_eq!(run(&LimitedCrane { capacity: 1 }), "DCBA");
+
+        let same_stack = Move {
+            count: 3,
+            to: 1,
+            ..mv
+        };
+        for crane in [
+            &CrateMover9000 as &dyn Crane,
+            &CrateMover9001,
+            &LimitedCrane { capacity: 2 },
+        ] {
+            let mut unchanged = stacks();
+            crane.apply(&mut unchanged, same_stack).unwrap();
+            assert_eq!(unchanged, stacks());
+            assert!(crane
+                .apply(
+                    &mut unchanged,
+                    Move {
+                        count: 5,
+                        ..same_stack
+                    }
+                )
+                .is_err());
+        }
+
+        let mut too_many = stacks();
+        assert!(CrateMover9001
+            .apply(&mut too_many, Move { count: 5, ..mv })
+            .is_err());
+        assert_eq!(too_many, stacks());
+        assert!(CrateMover9000
+            .apply(&mut stacks(), Move { from: 3, ..mv })
+            .is_err());
+        assert!(CrateMover9000
+            .apply(&mut stacks(), Move { to: 0, ..mv })
+            .is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let drawing = include_str!("../../data/day05/example.txt")
+            .split_once("\n\n")
+            .unwrap()
+            .0;
+        let stacks = parse_stacks(drawing).unwrap();
+        assert_eq!(render(&stacks), drawing);
+
+        let wide = vec![
+            vec!["AB".to_string()],
+            vec![],
+            vec!["C".to_string(), "D".to_string()],
+        ];
+        assert_eq!(
+            render(&wide),
+            "          [D] \n[AB]      [C] \n 1    2    3  "
+        );
+        assert_eq!(parse_stacks(&render(&wide)).unwrap(), wide);
+    }
+
+    #[test]
+    fn test_undo_round_trip() {
+        let data = process_input().unwrap();
+        let cranes: [&dyn Crane; 4] = [
+            &CrateMover9000,
+            &CrateMover9001,
+            &LimitedCrane { capacity: 2 },
+            &LimitedCrane { capacity: 1 },
+        ];
+        for crane in cranes {
+            let mut stacks = data.stacks.clone();
+            apply_all(crane, &mut stacks, &data.moves).unwrap();
+            undo_all(crane, &mut stacks, &data.moves).unwrap();
+            assert_eq!(stacks, data.stacks);
+        }
+
+        let target = stacks_with_tops(&data, "CMZ").unwrap();
+        let mut start = target.clone();
+        undo_all(&CrateMover9000, &mut start, &data.moves).unwrap();
+        apply_all(&CrateMover9000, &mut start, &data.moves).unwrap();
+        assert!(matches_target(&start, &target));
+        assert_eq!(top_crates(&start), "CMZ");
+        assert!(stacks_with_tops(&data, "CM").is_err());
+    }
+
+    #[test]
+    fn test_optimize_moves() {
+        let mv = |count, from, to| Move { count, from, to };
+        let moves = [
+            mv(1, 1, 2),
+            mv(2, 1, 2),
+            mv(0, 3, 1),
+            mv(3, 2, 3),
+            mv(3, 3, 2),
+            mv(1, 2, 2),
+            mv(1, 2, 1),
+        ];
+        assert_eq!(
+            optimize_moves(&CrateMover9000, &moves).unwrap(),
+            vec![mv(3, 1, 2), mv(1, 2, 1)]
+        );
+        // keeping crate order means consecutive moves can't be merged, only undone
+        assert_eq!(
+            optimize_moves(&CrateMover9001, &moves).unwrap(),
+            vec![mv(1, 1, 2), mv(2, 1, 2), mv(1, 2, 1)]
+        );
+        assert_eq!(
+            optimize_moves(&LimitedCrane { capacity: 2 }, &moves).unwrap(),
+            vec![
+                mv(1, 1, 2),
+                mv(2, 1, 2),
+                mv(3, 2, 3),
+                mv(3, 3, 2),
+                mv(1, 2, 1)
+            ]
+        );
+        assert_eq!(
+            optimize_moves(&LimitedCrane { capacity: 2 }, &[mv(2, 1, 2), mv(1, 1, 2)]).unwrap(),
+            vec![mv(3, 1, 2)]
+        );
+
+        // a same-stack move is dropped, and the stacks still end up the same without it
+        let stacks = vec![
+            ["A", "B", "C"].map(String::from).to_vec(),
+            vec!["D".to_string()],
+            vec![],
+        ];
+        let moves = [mv(2, 1, 1), mv(1, 1, 2), mv(2, 1, 1), mv(2, 2, 3)];
+        for crane in [
+            &CrateMover9000 as &dyn Crane,
+            &CrateMover9001,
+            &LimitedCrane { capacity: 1 },
+        ] {
+            let optimized = optimize_moves(crane, &moves).unwrap();
+            assert!(!optimized.contains(&mv(2, 1, 1)));
+            let (mut expected, mut actual) = (stacks.clone(), stacks.clone());
+            apply_all(crane, &mut expected, &moves).unwrap();
+            apply_all(crane, &mut actual, &optimized).unwrap();
+            assert_eq!(actual, expected);
+            assert_eq!(expected[0].concat(), "AB");
+        }
+    }
+}
@@ -1,6 +1,9 @@
-use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::PathBuf;
 
 use clap::Parser;
+use color_eyre::eyre::eyre;
 
 /// Argument template for Advent of Code
 #[derive(Parser, Debug)]
@@ -9,6 +12,18 @@ struct Args {
     /// Specify part to compute (1 or 2)
     #[arg(short, long, default_value_t = 1)]
     part: u8,
+
+    /// Scan this file instead of the puzzle input (`-` reads stdin)
+    #[arg(long)]
+    input: Option<PathBuf>,
+
+    /// Report every marker position instead of just the first one
+    #[arg(long)]
+    all: bool,
+
+    /// Treat every line as a separate stream and report both markers for each
+    #[arg(long)]
+    lines: bool,
 }
 
 fn get_input() -> &'static str {
@@ -16,46 +31,224 @@ fn get_input() -> &'static str {
     include_str!("../../data/day06/input.txt")
 }
 
-fn part1() -> color_eyre::Result<()> {
-    let n_distinct = 4usize;
+/// Finds markers in a stream fed one byte at a time, remembering only the last `k` bytes.
+///
+/// Keeps a count of every byte value in the sliding window and of how many values appear more
+/// than once, so each byte is handled in constant time whatever `k` is.
+struct MarkerScanner {
+    k: usize,
+    window: Vec<u8>,
+    counts: [usize; 256],
+    repeated: usize,
+    read: usize,
+}
 
-    let mut i = n_distinct;
-    for window in get_input().as_bytes().windows(n_distinct) {
-        let set: HashSet<u8> = HashSet::from_iter(window.iter().map(|b| *b));
-        if set.len() == n_distinct {
-            println!("Part 1 answer: {:?}", i);
-            break;
+impl MarkerScanner {
+    fn new(k: usize) -> Self {
+        MarkerScanner {
+            k,
+            window: vec![0; k],
+            counts: [0; 256],
+            repeated: 0,
+            read: 0,
         }
-        i += 1;
     }
-    println!("Part 1 done");
-    Ok(())
+
+    /// Adds the next byte, returning whether the last `k` bytes are all different.
+    fn push(&mut self, b: u8) -> bool {
+        if self.k == 0 {
+            self.read += 1;
+            return true;
+        }
+        let slot = self.read % self.k;
+        if self.read >= self.k {
+            let old = self.window[slot] as usize;
+            self.counts[old] -= 1;
+            if self.counts[old] == 1 {
+                self.repeated -= 1;
+            }
+        }
+        self.window[slot] = b;
+        self.counts[b as usize] += 1;
+        if self.counts[b as usize] == 2 {
+            self.repeated += 1;
+        }
+        self.read += 1;
+        self.read >= self.k && self.repeated == 0
+    }
+
+    /// Number of bytes pushed so far.
+    fn position(&self) -> usize {
+        self.read
+    }
+}
+
+/// Scans `reader` in fixed-size chunks, calling `on_marker` with the end offset of every marker
+/// until it returns false.
+fn scan_markers<R: Read>(
+    mut reader: R,
+    k: usize,
+    mut on_marker: impl FnMut(usize) -> bool,
+) -> io::Result<()> {
+    let mut scanner = MarkerScanner::new(k);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        for &b in &buf[..n] {
+            if scanner.push(b) && !on_marker(scanner.position()) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+/// Number of bytes read by the end of the first marker of `stream`, if any.
+fn find_marker(stream: &[u8], k: usize) -> Option<usize> {
+    let mut scanner = MarkerScanner::new(k);
+    stream
+        .iter()
+        .find(|&&b| scanner.push(b))
+        .map(|_| scanner.position())
+}
+
+fn open_input(path: &Option<PathBuf>) -> color_eyre::Result<Box<dyn Read>> {
+    Ok(match path {
+        None => Box::new(get_input().as_bytes()),
+        Some(path) if path.as_os_str() == "-" => Box::new(io::stdin().lock()),
+        Some(path) => Box::new(File::open(path)?),
+    })
 }
 
-fn part2() -> color_eyre::Result<()> {
-    let n_distinct = 14usize;
-    let mut i = n_distinct;
-    for window in get_input().as_bytes().windows(n_distinct) {
-        let set: HashSet<u8> = HashSet::from_iter(window.iter().map(|b| *b));
-        if set.len() == n_distinct {
-            println!("Part 2 answer: {:?}", i);
-            break;
+fn report_markers(args: &Args, k: usize, kind: &str) -> color_eyre::Result<Option<usize>> {
+    let mut first = None;
+    let mut count = 0usize;
+    let mut out = BufWriter::new(io::stdout().lock());
+    let mut written = Ok(());
+    scan_markers(open_input(&args.input)?, k, |position| {
+        first.get_or_insert(position);
+        count += 1;
+        if args.all {
+            written = writeln!(out, "{} marker at {}", kind, position);
         }
-        i += 1;
+        args.all && written.is_ok()
+    })?;
+    written?;
+    if args.all {
+        writeln!(out, "{} {} markers", count, kind)?;
+    }
+    out.flush()?;
+    Ok(first)
+}
+
+fn report_lines(args: &Args) -> color_eyre::Result<()> {
+    let show = |marker: Option<usize>| marker.map_or_else(|| "none".to_string(), |m| m.to_string());
+    for (i, line) in BufReader::new(open_input(&args.input)?).lines().enumerate() {
+        let line = line?;
+        println!(
+            "line {}: start-of-packet = {}, start-of-message = {}",
+            i + 1,
+            show(find_marker(line.as_bytes(), 4)),
+            show(find_marker(line.as_bytes(), 14))
+        );
     }
+    Ok(())
+}
+
+fn part1(args: &Args) -> color_eyre::Result<()> {
+    let marker = report_markers(args, 4, "start-of-packet")?
+        .ok_or_else(|| eyre!("no start-of-packet marker found"))?;
+    println!("Part 1 answer: {:?}", marker);
+    println!("Part 1 done");
+    Ok(())
+}
+
+fn part2(args: &Args) -> color_eyre::Result<()> {
+    let marker = report_markers(args, 14, "start-of-message")?
+        .ok_or_else(|| eyre!("no start-of-message marker found"))?;
+    println!("Part 2 answer: {:?}", marker);
     println!("Part 2 done");
     Ok(())
 }
 
 fn main() -> color_eyre::Result<()> {
-    let part = Args::parse().part;
-    println!("Running for part{}", part);
+    let args = Args::parse();
+    println!("Running for part{}", args.part);
+
+    if args.lines {
+        return report_lines(&args);
+    }
 
-    match Args::parse().part {
-        1 => part1()?,
-        2 => part2()?,
+    match args.part {
+        1 => part1(&args)?,
+        2 => part2(&args)?,
         _ => panic!("part argument not recognized"),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use aoc2022::rng::Rng;
+
+    use super::*;
+
+    fn naive_marker(stream: &[u8], k: usize) -> Option<usize> {
+        stream
+            .windows(k)
+            .position(|w| w.iter().collect::<HashSet<_>>().len() == k)
+            .map(|i| i + k)
+    }
+
+    #[test]
+    fn test_find_marker() {
+        let streams = include_str!("../../data/day06/example.txt").lines();
+        let examples = [(7, 19), (5, 23), (6, 23), (10, 29), (11, 26)];
+        for (stream, (packet, message)) in streams.zip(examples) {
+            assert_eq!(find_marker(stream.as_bytes(), 4), Some(packet));
+            assert_eq!(find_marker(stream.as_bytes(), 14), Some(message));
+        }
+        assert_eq!(find_marker(b"aaaa", 2), None);
+        assert_eq!(find_marker(b"ab", 3), None);
+    }
+
+    #[test]
+    fn test_find_marker_matches_naive() {
+        let mut rng = Rng::new(6);
+        for _ in 0..300 {
+            let stream = (0..200)
+                .map(|_| b'a' + rng.below(12) as u8)
+                .collect::<Vec<_>>();
+            for k in 1..=12 {
+                assert_eq!(find_marker(&stream, k), naive_marker(&stream, k));
+            }
+        }
+    }
+
+    #[test]
+    fn test_scan_markers() {
+        let stream = b"abcabcdd";
+        let mut markers = vec![];
+        scan_markers(&stream[..], 3, |position| {
+            markers.push(position);
+            true
+        })
+        .unwrap();
+        assert_eq!(markers, vec![3, 4, 5, 6, 7]);
+
+        let mut first = vec![];
+        scan_markers(&stream[..], 4, |position| {
+            first.push(position);
+            false
+        })
+        .unwrap();
+        assert_eq!(first, vec![7]);
+    }
+}
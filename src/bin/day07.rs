@@ -7,14 +7,57 @@ struct Args {
     /// Specify part to compute (1 or 2)
     #[arg(short, long, default_value_t = 1)]
     part: u8,
+
+    /// Print the reconstructed filesystem as an indented tree
+    #[arg(long)]
+    tree: bool,
+
+    /// Print the total size of every directory, biggest first
+    #[arg(long)]
+    du: bool,
+
+    /// Print only the N biggest directories
+    #[arg(long, value_name = "N")]
+    top: Option<usize>,
+
+    /// Size of the disk
+    #[arg(long, default_value_t = 70_000_000)]
+    total_space: u64,
+
+    /// Free space the update needs
+    #[arg(long, default_value_t = 30_000_000)]
+    needed_space: u64,
+
+    /// Free the space by deleting any number of directories instead of a single one
+    #[arg(long)]
+    multiple: bool,
+
+    /// Print the reconstructed filesystem as JSON
+    #[arg(long)]
+    json: bool,
+
+    /// Recreate the filesystem under DIR, with sparse files of the listed sizes
+    #[arg(long, value_name = "DIR")]
+    materialize: Option<PathBuf>,
+
+    /// Report inconsistencies in a transcript, the puzzle input if no FILE is given
+    #[arg(long, value_name = "FILE", num_args = 0..=1)]
+    check: Option<Option<PathBuf>>,
 }
 
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::eyre;
 use nom::{combinator::all_consuming, Finish};
 
 use nom::{
     branch::alt,
     bytes::complete::tag,
-    sequence::{preceded, separated_pair},
+    combinator::opt,
+    sequence::{pair, preceded, separated_pair},
     IResult,
 };
 
@@ -27,7 +70,7 @@ fn get_input() -> &'static str {
 
 fn parse_path(i: &str) -> IResult<&str, String> {
     map(
-        take_while1(|c: char| c.is_alphabetic() || "./".contains(c)),
+        take_while1(|c: char| c.is_alphanumeric() || "./-_".contains(c)),
         Into::into,
     )(i)
 }
@@ -49,6 +92,20 @@ fn parse_cd(i: &str) -> IResult<&str, Cd> {
 enum Command {
     Ls,
     Cd(String),
+    /// `mkdir <path>`, creating missing parents too
+    Mkdir(String),
+    /// `rm [-r] <path>`, directories need `-r`
+    Rm {
+        path: String,
+        recursive: bool,
+    },
+    /// `touch <path>`, creating an empty file if there's nothing there yet
+    Touch(String),
+    /// `cat <size> <path>`, writing a file of `size` bytes
+    Cat {
+        size: u64,
+        path: String,
+    },
 }
 
 impl From<Ls> for Command {
@@ -63,9 +120,42 @@ impl From<Cd> for Command {
     }
 }
 
+fn parse_mkdir(i: &str) -> IResult<&str, Command> {
+    map(preceded(tag("mkdir "), parse_path), Command::Mkdir)(i)
+}
+
+fn parse_rm(i: &str) -> IResult<&str, Command> {
+    let recursive = map(opt(alt((tag("-rf "), tag("-r ")))), |flag| flag.is_some());
+    map(
+        preceded(tag("rm "), pair(recursive, parse_path)),
+        |(recursive, path)| Command::Rm { path, recursive },
+    )(i)
+}
+
+fn parse_touch(i: &str) -> IResult<&str, Command> {
+    map(preceded(tag("touch "), parse_path), Command::Touch)(i)
+}
+
+fn parse_cat(i: &str) -> IResult<&str, Command> {
+    map(
+        preceded(
+            tag("cat "),
+            separated_pair(nom::character::complete::u64, tag(" "), parse_path),
+        ),
+        |(size, path)| Command::Cat { size, path },
+    )(i)
+}
+
 fn parse_command(i: &str) -> IResult<&str, Command> {
     let (i, _) = tag("$ ")(i)?;
-    alt((map(parse_ls, Into::into), map(parse_cd, Into::into)))(i)
+    alt((
+        map(parse_ls, Into::into),
+        map(parse_cd, Into::into),
+        parse_mkdir,
+        parse_rm,
+        parse_touch,
+        parse_cat,
+    ))(i)
 }
 
 #[derive(Debug)]
@@ -97,91 +187,594 @@ fn parse_line(i: &str) -> IResult<&str, Line> {
     ))(i)
 }
 
+/// Makes sure `name` is a single path component, so that every node can be reached by its path.
+fn check_name(name: &str) -> color_eyre::Result<()> {
+    if matches!(name, "" | "." | "..") || name.contains('/') {
+        return Err(eyre!("{:?} isn't a valid file or directory name", name));
+    }
+    Ok(())
+}
+
+/// Index of a node in the `VirtualFs` arena.
+type NodeId = usize;
+
+/// What `VirtualFs::walk` reports to its callback while it follows a path.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    /// Moved into a directory, starting with the one the path is relative to.
+    Entered(NodeId),
+    /// A `..` at the root, the walk stays there if the callback lets it go on.
+    AboveRoot,
+}
+
 #[derive(Debug)]
-struct FsEntry {
-    path: String,
-    size: u64,
-    children: Vec<FsEntry>,
+enum NodeKind {
+    File { size: u64 },
+    Dir { children: BTreeMap<String, NodeId> },
 }
 
-impl FsEntry {
-    fn total_size(&self) -> u64 {
-        self.size + self.children.iter().map(|c| c.total_size()).sum::<u64>()
+#[derive(Debug)]
+struct Node {
+    name: String,
+    parent: Option<NodeId>,
+    kind: NodeKind,
+    /// Size of the file, or of everything below the directory, once sizes are computed.
+    total_size: u64,
+}
+
+impl Node {
+    fn is_dir(&self) -> bool {
+        matches!(self.kind, NodeKind::Dir { .. })
     }
 
-    fn all_dirs(&self) -> Box<dyn Iterator<Item = &FsEntry> + '_> {
-        Box::new(
-            std::iter::once(self).chain(
-                self.children
-                    .iter()
-                    .filter(|c| !c.children.is_empty())
-                    .flat_map(|c| c.all_dirs()),
-            ),
-        )
+    /// Children of a directory in name order, nothing for a file.
+    fn children(&self) -> impl Iterator<Item = NodeId> + '_ {
+        match &self.kind {
+            NodeKind::Dir { children } => Some(children.values().copied()),
+            NodeKind::File { .. } => None,
+        }
+        .into_iter()
+        .flatten()
     }
 }
 
-fn part1() -> color_eyre::Result<()> {
-    let lines = get_input()
-        .lines()
-        .map(|l| all_consuming(parse_line)(l).finish().unwrap().1);
-
-    let mut stack = vec![FsEntry {
-        path: "/".into(),
-        size: 0,
-        children: vec![],
-    }];
-
-    for line in lines {
-        println!("{line:?}");
+/// The filesystem rebuilt from a terminal transcript, with every node stored in one arena.
+///
+/// Nodes are only ever appended, so a child always comes after its parent in the arena.
+#[derive(Debug)]
+struct VirtualFs {
+    nodes: Vec<Node>,
+    cwd: NodeId,
+    /// Whether `Node::total_size` is up to date and kept that way as entries are added.
+    sized: bool,
+}
+
+impl VirtualFs {
+    const ROOT: NodeId = 0;
+
+    fn new() -> Self {
+        VirtualFs {
+            nodes: vec![Node {
+                name: "/".into(),
+                parent: None,
+                kind: NodeKind::Dir {
+                    children: BTreeMap::new(),
+                },
+                total_size: 0,
+            }],
+            cwd: Self::ROOT,
+            sized: false,
+        }
+    }
+
+    fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    /// Returns the child of `dir` called `name`, adding it as `kind` first if it's new.
+    ///
+    /// An existing child keeps its contents but has to be of the same kind.
+    fn get_or_insert(
+        &mut self,
+        dir: NodeId,
+        name: &str,
+        kind: NodeKind,
+    ) -> color_eyre::Result<NodeId> {
+        check_name(name)?;
+        let NodeKind::Dir { children } = &self.nodes[dir].kind else {
+            return Err(eyre!("{} is a file, not a directory", self.path(dir)));
+        };
+        if let Some(&id) = children.get(name) {
+            if self.node(id).is_dir() != matches!(kind, NodeKind::Dir { .. }) {
+                return Err(eyre!(
+                    "{} is already listed as a {}",
+                    self.path(id),
+                    if self.node(id).is_dir() {
+                        "directory"
+                    } else {
+                        "file"
+                    }
+                ));
+            }
+            return Ok(id);
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            name: name.into(),
+            parent: Some(dir),
+            kind,
+            total_size: 0,
+        });
+        if let NodeKind::Dir { children } = &mut self.nodes[dir].kind {
+            children.insert(name.into(), id);
+        }
+        Ok(id)
+    }
+
+    fn get_or_insert_dir(&mut self, dir: NodeId, name: &str) -> color_eyre::Result<NodeId> {
+        let kind = NodeKind::Dir {
+            children: BTreeMap::new(),
+        };
+        self.get_or_insert(dir, name, kind)
+    }
+
+    /// Follows `path` from the current directory, or from the root if it starts with `/`.
+    ///
+    /// Directories along the way that weren't listed yet are added.
+    fn resolve(&mut self, path: &str) -> color_eyre::Result<NodeId> {
+        self.walk(path, true, Self::strict)
+    }
+
+    /// Like `resolve`, but `path` has to exist already.
+    fn lookup(&mut self, path: &str) -> color_eyre::Result<NodeId> {
+        self.walk(path, false, Self::strict)
+    }
+
+    /// The `walk` callback used everywhere but `check_transcript`, it only refuses a `..` at
+    /// the root.
+    fn strict(&self, step: Step) -> color_eyre::Result<()> {
+        match step {
+            Step::Entered(_) => Ok(()),
+            Step::AboveRoot => Err(eyre!("can't go above the root directory")),
+        }
+    }
+
+    /// Follows `path` one component at a time, telling `visit` about every step. An error from
+    /// `visit` stops the walk.
+    fn walk(
+        &mut self,
+        path: &str,
+        create: bool,
+        mut visit: impl FnMut(&Self, Step) -> color_eyre::Result<()>,
+    ) -> color_eyre::Result<NodeId> {
+        let mut id = if path.starts_with('/') {
+            Self::ROOT
+        } else {
+            self.cwd
+        };
+        visit(self, Step::Entered(id))?;
+        for component in path.split('/') {
+            id = match component {
+                "" | "." => continue,
+                ".." => match self.nodes[id].parent {
+                    Some(parent) => parent,
+                    None => {
+                        visit(self, Step::AboveRoot)?;
+                        continue;
+                    }
+                },
+                name if create => self.get_or_insert_dir(id, name)?,
+                name => self.child(id, name).ok_or_else(|| {
+                    eyre!(
+                        "{}/{} doesn't exist",
+                        self.path(id).trim_end_matches('/'),
+                        name
+                    )
+                })?,
+            };
+            visit(self, Step::Entered(id))?;
+        }
+        Ok(id)
+    }
+
+    fn child(&self, dir: NodeId, name: &str) -> Option<NodeId> {
+        match &self.node(dir).kind {
+            NodeKind::Dir { children } => children.get(name).copied(),
+            NodeKind::File { .. } => None,
+        }
+    }
+
+    /// Splits `path` into the directory it's in, created if needed, and its last component.
+    fn resolve_parent<'p>(&mut self, path: &'p str) -> color_eyre::Result<(NodeId, &'p str)> {
+        let trimmed = path.trim_end_matches('/');
+        let (dir, name) = match trimmed.rsplit_once('/') {
+            Some(("", name)) => (Self::ROOT, name),
+            Some((parent, name)) => (self.resolve(parent)?, name),
+            None => (self.cwd, trimmed),
+        };
+        check_name(name)?;
+        Ok((dir, name))
+    }
+
+    fn cd(&mut self, path: &str) -> color_eyre::Result<()> {
+        self.cwd = self.resolve(path)?;
+        Ok(())
+    }
+
+    /// Adds an `ls` entry to the current directory, listing a directory again just updates it.
+    fn add_entry(&mut self, entry: Entry) -> color_eyre::Result<()> {
+        match entry {
+            Entry::Dir(name) => {
+                self.get_or_insert_dir(self.cwd, &name)?;
+            }
+            Entry::File(size, name) => self.write_file(self.cwd, &name, size)?,
+        }
+        Ok(())
+    }
+
+    /// Creates the file `name` in `dir` or changes its size.
+    fn write_file(&mut self, dir: NodeId, name: &str, size: u64) -> color_eyre::Result<()> {
+        let id = self.get_or_insert(dir, name, NodeKind::File { size })?;
+        self.nodes[id].kind = NodeKind::File { size };
+        if self.sized {
+            let old = self.nodes[id].total_size;
+            self.update_sizes(id, |total| total - old + size);
+        }
+        Ok(())
+    }
+
+    fn touch(&mut self, path: &str) -> color_eyre::Result<()> {
+        let (dir, name) = self.resolve_parent(path)?;
+        if self.child(dir, name).is_none() {
+            self.write_file(dir, name, 0)?;
+        }
+        Ok(())
+    }
+
+    fn cat(&mut self, path: &str, size: u64) -> color_eyre::Result<()> {
+        let (dir, name) = self.resolve_parent(path)?;
+        self.write_file(dir, name, size)
+    }
+
+    /// Detaches a node from the tree. It stays in the arena but can't be reached any more.
+    fn rm(&mut self, path: &str, recursive: bool) -> color_eyre::Result<()> {
+        let id = self.lookup(path)?;
+        if self.node(id).is_dir() && !recursive {
+            return Err(eyre!("{} is a directory", self.path(id)));
+        }
+        let mut current = Some(self.cwd);
+        while let Some(dir) = current {
+            if dir == id {
+                return Err(eyre!(
+                    "can't remove {}, it holds the current directory",
+                    self.path(id)
+                ));
+            }
+            current = self.node(dir).parent;
+        }
+
+        let parent = self.nodes[id]
+            .parent
+            .take()
+            .expect("only the root has no parent");
+        let name = self.nodes[id].name.clone();
+        if let NodeKind::Dir { children } = &mut self.nodes[parent].kind {
+            children.remove(&name);
+        }
+        if self.sized {
+            let size = self.nodes[id].total_size;
+            self.update_sizes(parent, |total| total - size);
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, line: Line) -> color_eyre::Result<()> {
         match line {
-            Line::Command(cmd) => match cmd {
-                Command::Ls => {
-                    // just ignore those
+            Line::Command(Command::Ls) => Ok(()),
+            Line::Command(Command::Cd(path)) => self.cd(&path),
+            Line::Command(Command::Mkdir(path)) => self.resolve(&path).map(|_| ()),
+            Line::Command(Command::Rm { path, recursive }) => self.rm(&path, recursive),
+            Line::Command(Command::Touch(path)) => self.touch(&path),
+            Line::Command(Command::Cat { size, path }) => self.cat(&path, size),
+            Line::Entry(entry) => self.add_entry(entry),
+        }
+    }
+
+    /// Absolute path of a node, like `/a/e`.
+    fn path(&self, id: NodeId) -> String {
+        let mut names = vec![];
+        let mut current = Some(id);
+        while let Some(id) = current.filter(|&id| id != Self::ROOT) {
+            names.push(self.node(id).name.as_str());
+            current = self.node(id).parent;
+        }
+        names.reverse();
+        format!("/{}", names.join("/"))
+    }
+
+    /// Computes every node's total size in one post-order pass, then keeps them up to date.
+    fn compute_sizes(&mut self) {
+        for node in &mut self.nodes {
+            node.total_size = match node.kind {
+                NodeKind::File { size } => size,
+                NodeKind::Dir { .. } => 0,
+            };
+        }
+        // children come after their parents, so going backwards visits them first
+        for id in (1..self.nodes.len()).rev() {
+            let (size, parent) = (self.nodes[id].total_size, self.nodes[id].parent);
+            if let Some(parent) = parent {
+                self.nodes[parent].total_size += size;
+            }
+        }
+        self.sized = true;
+    }
+
+    /// Applies `f` to the total size of `id` and of all its ancestors.
+    fn update_sizes(&mut self, id: NodeId, f: impl Fn(u64) -> u64) {
+        let mut current = Some(id);
+        while let Some(id) = current {
+            self.nodes[id].total_size = f(self.nodes[id].total_size);
+            current = self.nodes[id].parent;
+        }
+    }
+
+    fn total_size(&self, id: NodeId) -> u64 {
+        debug_assert!(self.sized, "sizes haven't been computed yet");
+        self.node(id).total_size
+    }
+
+    /// Every directory still in the tree, the root and empty ones included, in pre-order.
+    fn all_dirs(&self) -> impl Iterator<Item = NodeId> + '_ {
+        let mut stack = vec![Self::ROOT];
+        std::iter::from_fn(move || {
+            let id = stack.pop()?;
+            let dirs = self.node(id).children().filter(|&c| self.node(c).is_dir());
+            stack.extend(dirs.collect::<Vec<_>>().into_iter().rev());
+            Some(id)
+        })
+    }
+
+    /// Draws the tree below `id` the way the puzzle statement does, with directory sizes.
+    fn render_tree(&self, id: NodeId) -> String {
+        let mut out = String::new();
+        let mut stack = vec![(id, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let node = self.node(id);
+            let kind = if node.is_dir() { "dir" } else { "file" };
+            out.push_str(&format!(
+                "{}- {} ({}, size={})\n",
+                "  ".repeat(depth),
+                node.name,
+                kind,
+                self.total_size(id)
+            ));
+            let children = node.children().collect::<Vec<_>>();
+            stack.extend(children.into_iter().rev().map(|c| (c, depth + 1)));
+        }
+        out
+    }
+
+    /// Directories with their absolute path and total size, biggest first.
+    fn du(&self) -> Vec<(u64, String)> {
+        let mut dirs = self
+            .all_dirs()
+            .map(|id| (self.total_size(id), self.path(id)))
+            .collect::<Vec<_>>();
+        dirs.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+        dirs
+    }
+
+    /// The tree below `id` as JSON objects with `path`, `kind`, `size` and, for directories,
+    /// `children`.
+    fn to_json(&self, id: NodeId) -> String {
+        fn quote(s: &str) -> String {
+            let mut out = String::from('"');
+            for c in s.chars() {
+                match c {
+                    '"' => out.push_str("\\\""),
+                    '\\' => out.push_str("\\\\"),
+                    c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+                    c => out.push(c),
                 }
-                Command::Cd(path) => match path.as_str() {
-                    "/" => {
-                        // ignore, we're already there
-                    }
-                    ".." => {
-                        let child = stack.pop();
-                        stack.last_mut().unwrap().children.push(child.unwrap());
+            }
+            out.push('"');
+            out
+        }
+
+        fn write(fs: &VirtualFs, id: NodeId, depth: usize, out: &mut String) {
+            let node = fs.node(id);
+            let indent = "  ".repeat(depth + 1);
+            out.push_str("{\n");
+            out.push_str(&format!("{}\"path\": {},\n", indent, quote(&fs.path(id))));
+            let kind = if node.is_dir() { "dir" } else { "file" };
+            out.push_str(&format!("{}\"kind\": \"{}\",\n", indent, kind));
+            out.push_str(&format!("{}\"size\": {}", indent, fs.total_size(id)));
+            if node.is_dir() {
+                out.push_str(&format!(",\n{}\"children\": [", indent));
+                for (i, child) in node.children().enumerate() {
+                    out.push_str(if i == 0 { "\n" } else { ",\n" });
+                    out.push_str(&"  ".repeat(depth + 2));
+                    write(fs, child, depth + 2, out);
+                }
+                if node.children().next().is_some() {
+                    out.push('\n');
+                    out.push_str(&indent);
+                }
+                out.push(']');
+            }
+            out.push('\n');
+            out.push_str(&"  ".repeat(depth));
+            out.push('}');
+        }
+
+        let mut out = String::new();
+        write(self, id, 0, &mut out);
+        out.push('\n');
+        out
+    }
+
+    /// Recreates the tree under `target`, which must not exist or be empty. Files are sparse,
+    /// so they take the listed size without using the disk space.
+    ///
+    /// Every name is checked before anything is written, so nothing can end up outside `target`.
+    fn materialize(&self, target: &Path) -> color_eyre::Result<()> {
+        let mut paths = vec![];
+        let mut stack = vec![(Self::ROOT, target.to_path_buf())];
+        while let Some((id, path)) = stack.pop() {
+            for child in self.node(id).children() {
+                let name = &self.node(child).name;
+                check_name(name)?;
+                stack.push((child, path.join(name)));
+            }
+            paths.push((id, path));
+        }
+
+        if target.exists() && fs::read_dir(target)?.next().is_some() {
+            return Err(eyre!("{} isn't empty", target.display()));
+        }
+        for (id, path) in paths {
+            match self.node(id).kind {
+                NodeKind::File { size } => File::create(&path)?.set_len(size)?,
+                NodeKind::Dir { .. } => fs::create_dir_all(&path)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Replays a transcript, echoing each parsed line when `echo` is set.
+fn build_fs(transcript: &str, echo: bool) -> color_eyre::Result<VirtualFs> {
+    let mut fs = VirtualFs::new();
+    for (i, l) in transcript.lines().enumerate() {
+        let line = parse_numbered(i, l)?;
+        if echo {
+            println!("{line:?}");
+        }
+        fs.apply(line).map_err(|e| eyre!("line {}: {}", i + 1, e))?;
+    }
+    fs.compute_sizes();
+    Ok(fs)
+}
+
+fn parse_numbered(i: usize, l: &str) -> color_eyre::Result<Line> {
+    Ok(all_consuming(parse_line)(l)
+        .finish()
+        .map_err(|e| eyre!("line {}: can't parse {:?}: {:?}", i + 1, l, e.code))?
+        .1)
+}
+
+/// Something in a transcript that doesn't add up, with its 1-based line number.
+#[derive(Debug, PartialEq, Eq)]
+struct Warning {
+    line: usize,
+    message: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+/// Replays a transcript leniently and reports the lines that look wrong instead of failing on
+/// the first one. Only lines that don't parse at all are errors.
+fn check_transcript(transcript: &str) -> color_eyre::Result<Vec<Warning>> {
+    let mut fs = VirtualFs::new();
+    let mut warnings = vec![];
+    // directories the transcript has shown us, either listed with `dir` or made with `mkdir`
+    let mut listed = HashSet::from([VirtualFs::ROOT]);
+    let mut listing = false;
+
+    for (i, l) in transcript.lines().enumerate() {
+        let mut warn = |message: String| {
+            warnings.push(Warning {
+                line: i + 1,
+                message,
+            })
+        };
+        let line = parse_numbered(i, l)?;
+        match &line {
+            Line::Command(Command::Ls) => listing = true,
+            Line::Command(_) => listing = false,
+            Line::Entry(_) if !listing => {
+                warn("ls output without a preceding `ls`".into());
+                listing = true;
+            }
+            Line::Entry(_) => {}
+        }
+
+        match line {
+            Line::Command(Command::Cd(path)) => {
+                // a `cd` that goes wrong halfway leaves us in the last directory it reached
+                let mut here = fs.cwd;
+                let walked = fs.walk(&path, true, |fs, step| {
+                    match step {
+                        Step::Entered(id) => {
+                            here = id;
+                            if listed.insert(id) {
+                                warn(format!("`cd` into {}, which was never listed", fs.path(id)));
+                            }
+                        }
+                        Step::AboveRoot => {
+                            warn("`cd ..` past the root directory, staying at /".into())
+                        }
                     }
-                    _ => {
-                        let node = FsEntry {
-                            path: path.clone(),
-                            size: 0,
-                            children: vec![],
-                        };
-                        stack.push(node);
+                    Ok(())
+                });
+                if let Err(e) = walked {
+                    warn(e.to_string());
+                }
+                fs.cwd = here;
+            }
+            Line::Command(Command::Mkdir(path)) => match fs.resolve(&path) {
+                Ok(mut id) => {
+                    while listed.insert(id) {
+                        id = fs.node(id).parent.unwrap_or(VirtualFs::ROOT);
                     }
-                },
+                }
+                Err(e) => warn(e.to_string()),
             },
-            Line::Entry(entry) => match entry {
-                Entry::Dir(_) => {
-                    // ignore, we'll do that when we `cd` into them
+            Line::Entry(Entry::File(size, name)) => {
+                let old = fs.child(fs.cwd, &name).map(|id| &fs.node(id).kind);
+                if let Some(&NodeKind::File { size: old }) = old {
+                    if old != size {
+                        warn(format!(
+                            "{}/{} listed again with size {} instead of {}",
+                            fs.path(fs.cwd).trim_end_matches('/'),
+                            name,
+                            size,
+                            old
+                        ));
+                    }
                 }
-                Entry::File(size, path) => {
-                    let node = FsEntry {
-                        size,
-                        path,
-                        children: vec![],
-                    };
-                    stack.last_mut().unwrap().children.push(node);
+                if let Err(e) = fs.add_entry(Entry::File(size, name)) {
+                    warn(e.to_string());
                 }
+            }
+            Line::Entry(Entry::Dir(name)) => match fs.get_or_insert_dir(fs.cwd, &name) {
+                Ok(id) => {
+                    listed.insert(id);
+                }
+                Err(e) => warn(e.to_string()),
             },
+            line => {
+                if let Err(e) = fs.apply(line) {
+                    warn(e.to_string());
+                }
+            }
         }
     }
-    let mut root = stack.pop().unwrap();
-    while let Some(mut next) = stack.pop() {
-        next.children.push(root);
-        root = next;
-    }
-    dbg!(&root);
+    Ok(warnings)
+}
+
+fn part1() -> color_eyre::Result<()> {
+    let fs = build_fs(get_input(), true)?;
     // solving part 1 because it's the same difficulty as part 2, just less code
-    let sum = root
+    let sum = fs
         .all_dirs()
-        .map(|d| d.total_size())
+        .map(|d| fs.total_size(d))
         .filter(|&s| s <= 100_000)
         .sum::<u64>();
     dbg!(sum);
@@ -189,78 +782,194 @@ fn part1() -> color_eyre::Result<()> {
     Ok(())
 }
 
-fn part2() -> color_eyre::Result<()> {
-    let lines = get_input()
-        .lines()
-        .map(|l| all_consuming(parse_line)(l).finish().unwrap().1);
+/// Space that has to be freed, `None` if there's already enough.
+fn space_to_free(
+    fs: &VirtualFs,
+    total_space: u64,
+    needed_space: u64,
+) -> color_eyre::Result<Option<u64>> {
+    let used_space = fs.total_size(VirtualFs::ROOT);
+    let free_space = total_space.checked_sub(used_space).ok_or_else(|| {
+        eyre!(
+            "{} bytes are used but the disk only holds {}",
+            used_space,
+            total_space
+        )
+    })?;
+    Ok(needed_space.checked_sub(free_space).filter(|&n| n > 0))
+}
 
-    let mut stack = vec![FsEntry {
-        path: "/".into(),
-        size: 0,
-        children: vec![],
-    }];
+/// A set of byte counts below some limit, one bit per count.
+#[derive(Debug, Clone)]
+struct SumSet {
+    words: Vec<u64>,
+    limit: u64,
+}
 
-    for line in lines {
-        println!("{line:?}");
-        match line {
-            Line::Command(cmd) => match cmd {
-                Command::Ls => {
-                    // just ignore those
-                }
-                Command::Cd(path) => match path.as_str() {
-                    "/" => {
-                        // ignore, we're already there
-                    }
-                    ".." => {
-                        let child = stack.pop();
-                        stack.last_mut().unwrap().children.push(child.unwrap());
-                    }
-                    _ => {
-                        let node = FsEntry {
-                            path: path.clone(),
-                            size: 0,
-                            children: vec![],
-                        };
-                        stack.push(node);
-                    }
-                },
-            },
-            Line::Entry(entry) => match entry {
-                Entry::Dir(_) => {
-                    // ignore, we'll do that when we `cd` into them
-                }
-                Entry::File(size, path) => {
-                    let node = FsEntry {
-                        size,
-                        path,
-                        children: vec![],
-                    };
-                    stack.last_mut().unwrap().children.push(node);
-                }
-            },
+impl SumSet {
+    /// The set holding only 0.
+    fn zero(limit: u64) -> Self {
+        let mut words = vec![0; limit.div_ceil(64) as usize];
+        words[0] = 1;
+        SumSet { words, limit }
+    }
+
+    fn contains(&self, sum: u64) -> bool {
+        sum < self.limit && self.words[(sum / 64) as usize] & 1 << (sum % 64) != 0
+    }
+
+    /// Adds `shift` plus every count of `other`, dropping whatever reaches the limit.
+    fn union_shifted(&mut self, other: &SumSet, shift: u64) {
+        let (skip, bits) = ((shift / 64) as usize, shift % 64);
+        for i in skip..self.words.len() {
+            let low = other.words[i - skip] << bits;
+            let high = match (i > skip, bits) {
+                (true, 1..) => other.words[i - skip - 1] >> (64 - bits),
+                _ => 0,
+            };
+            self.words[i] |= low | high;
+        }
+        if !self.limit.is_multiple_of(64) {
+            *self.words.last_mut().unwrap() &= (1 << (self.limit % 64)) - 1;
         }
     }
-    let mut root = stack.pop().unwrap();
-    while let Some(mut next) = stack.pop() {
-        next.children.push(root);
-        root = next;
+
+    fn first_at_least(&self, sum: u64) -> Option<u64> {
+        (sum..self.limit).find(|&s| self.contains(s))
     }
-    dbg!(&root);
+}
+
+/// Largest directory size `dirs_to_free` combines directories for. Its sets have a bit per byte,
+/// so this keeps each of them at 16 MiB.
+const MAX_COMBINED_SIZE: u64 = 1 << 27;
 
-    let total_space = 70000000_u64;
-    let used_space = root.total_size();
-    let free_space = total_space.checked_sub(dbg!(used_space)).unwrap();
-    let needed_free_space = 30000000_u64;
-    let minimum_space_to_free = needed_free_space.checked_sub(free_space).unwrap();
+/// Walks the directories backwards, working out for each index `i` the sums below `limit`
+/// that deleting directories from `dirs[i..]` can free, until `visit` returns true.
+///
+/// `dirs` holds each directory's size and the index just past its subtree, in pre-order.
+fn walk_freeable(dirs: &[(u64, usize)], limit: u64, mut visit: impl FnMut(usize, &SumSet) -> bool) {
+    // the last index that still needs each set, so only a path's worth is kept around
+    let mut last_use = (0..=dirs.len())
+        .map(|j| j.saturating_sub(1))
+        .collect::<Vec<_>>();
+    for (i, &(_, end)) in dirs.iter().enumerate() {
+        last_use[end] = last_use[end].min(i);
+    }
+    let mut drop_after = vec![vec![]; dirs.len()];
+    for (j, &i) in last_use.iter().enumerate().skip(1) {
+        drop_after[i].push(j);
+    }
 
-    let size_to_remove = root
+    let mut sets = vec![None; dirs.len() + 1];
+    sets[dirs.len()] = Some(SumSet::zero(limit));
+    for (i, &(size, end)) in dirs.iter().enumerate().rev() {
+        let mut set = sets[i + 1].clone().unwrap();
+        if size < limit {
+            set.union_shifted(sets[end].as_ref().unwrap(), size);
+        }
+        if visit(i, &set) {
+            return;
+        }
+        sets[i] = Some(set);
+        for &j in &drop_after[i] {
+            sets[j] = None;
+        }
+    }
+}
+
+/// The directories to delete to free at least `target` bytes while deleting as little as
+/// possible. Directories are never nested in each other.
+///
+/// One walk finds how much to free, then every deleted directory takes another walk to find, so
+/// this is O(deleted · directories · size / 64). It refuses to combine directories when even the
+/// best single one is bigger than `MAX_COMBINED_SIZE`.
+fn dirs_to_free(fs: &VirtualFs, target: u64) -> color_eyre::Result<Option<(u64, Vec<NodeId>)>> {
+    fn visit(fs: &VirtualFs, id: NodeId, order: &mut Vec<NodeId>, dirs: &mut Vec<(u64, usize)>) {
+        let index = dirs.len();
+        order.push(id);
+        dirs.push((fs.total_size(id), 0));
+        for child in fs.node(id).children().filter(|&c| fs.node(c).is_dir()) {
+            visit(fs, child, order, dirs);
+        }
+        dirs[index].1 = dirs.len();
+    }
+
+    let (mut order, mut dirs) = (vec![], vec![]);
+    visit(fs, VirtualFs::ROOT, &mut order, &mut dirs);
+
+    // only combinations freeing less than the best single directory are worth a look
+    let Some((single, limit)) = (0..dirs.len())
+        .filter(|&i| dirs[i].0 >= target)
+        .map(|i| (i, dirs[i].0))
+        .min_by_key(|&(_, size)| size)
+    else {
+        return Ok(None);
+    };
+    if limit > MAX_COMBINED_SIZE {
+        return Err(eyre!(
+            "can only combine directories up to {} bytes, but the smallest one freeing enough \
+             holds {}",
+            MAX_COMBINED_SIZE,
+            limit
+        ));
+    }
+    let mut freed = None;
+    walk_freeable(&dirs, limit, |i, set| {
+        if i == 0 {
+            freed = set.first_at_least(target);
+        }
+        false
+    });
+    let Some(freed) = freed else {
+        return Ok(Some((limit, vec![order[single]])));
+    };
+
+    // delete the last directory whose set still holds what's left, then carry on after it
+    let (mut picked, mut left, mut from) = (vec![], freed, 0);
+    while left > 0 {
+        let mut last = 0;
+        walk_freeable(&dirs, limit, |i, set| {
+            last = i;
+            i >= from && set.contains(left)
+        });
+        picked.push(order[last]);
+        left -= dirs[last].0;
+        from = dirs[last].1;
+    }
+    Ok(Some((freed, picked)))
+}
+
+fn part2(total_space: u64, needed_space: u64, multiple: bool) -> color_eyre::Result<()> {
+    let fs = build_fs(get_input(), true)?;
+
+    let Some(minimum_space_to_free) = space_to_free(&fs, total_space, needed_space)? else {
+        println!("no deletion needed, there is already enough free space");
+        return Ok(());
+    };
+    dbg!(minimum_space_to_free);
+
+    if multiple {
+        let (size, dirs) = dirs_to_free(&fs, minimum_space_to_free)?
+            .ok_or_else(|| eyre!("deleting everything still doesn't free enough space"))?;
+        for dir in dirs {
+            println!("{}\t{}", fs.total_size(dir), fs.path(dir));
+        }
+        println!("{}\ttotal", size);
+        return Ok(());
+    }
+
+    let to_remove = fs
         .all_dirs()
-        .map(|n| n.total_size())
-        .filter(|&s| s >= minimum_space_to_free)
-        .inspect(|s| {
+        .map(|n| (fs.total_size(n), n))
+        .filter(|&(s, _)| s >= minimum_space_to_free)
+        .inspect(|(s, _)| {
             dbg!(s);
         })
         .min();
+    let size_to_remove = to_remove.map(|(s, _)| s);
+    if let Some((size, dir)) = to_remove {
+        println!("{}\t{}", size, fs.path(dir));
+    }
     dbg!(size_to_remove);
 
     Ok(())
@@ -269,12 +978,275 @@ fn part2() -> color_eyre::Result<()> {
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
 
-    let part = Args::parse().part;
-    println!("Running for part{}", part);
-    match Args::parse().part {
+    let args = Args::parse();
+    if let Some(path) = &args.check {
+        let transcript = match path {
+            Some(path) => fs::read_to_string(path)?,
+            None => get_input().to_string(),
+        };
+        let warnings = check_transcript(&transcript)?;
+        for warning in &warnings {
+            println!("{}", warning);
+        }
+        println!("{} warnings", warnings.len());
+        return Ok(());
+    }
+    if args.tree || args.du || args.top.is_some() || args.json || args.materialize.is_some() {
+        let fs = build_fs(get_input(), false)?;
+        if args.tree {
+            print!("{}", fs.render_tree(VirtualFs::ROOT));
+        }
+        if args.du || args.top.is_some() {
+            let limit = args.top.unwrap_or(usize::MAX);
+            for (size, path) in fs.du().into_iter().take(limit) {
+                println!("{}\t{}", size, path);
+            }
+        }
+        if args.json {
+            print!("{}", fs.to_json(VirtualFs::ROOT));
+        }
+        if let Some(target) = &args.materialize {
+            fs.materialize(target)?;
+            println!("materialized into {}", target.display());
+        }
+        return Ok(());
+    }
+
+    println!("Running for part{}", args.part);
+    match args.part {
         1 => part1()?,
-        2 => part2()?,
+        2 => part2(args.total_space, args.needed_space, args.multiple)?,
         _ => panic!("part argument not recognized"),
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(transcript: &str) -> color_eyre::Result<VirtualFs> {
+        build_fs(transcript, false)
+    }
+
+    #[test]
+    fn test_virtual_fs() {
+        let fs = run(include_str!("../../data/day07/example.txt")).unwrap();
+        assert_eq!(fs.total_size(VirtualFs::ROOT), 48381165);
+
+        // absolute paths, `cd /` mid-session and listing a directory twice
+        let fs = run("$ cd /\n$ ls\ndir a\n1 b\n$ cd a\n$ ls\n10 c\n$ cd /\n$ cd /a\n$ ls\n10 c\n$ cd ../a/.\n$ ls\n20 c")
+            .unwrap();
+        assert_eq!(fs.nodes.len(), 4);
+        assert_eq!(fs.path(fs.cwd), "/a");
+        assert_eq!(fs.total_size(VirtualFs::ROOT), 21);
+
+        assert!(run("$ cd /\n$ cd ..").is_err());
+
+        // an empty directory is still a directory, an empty file isn't
+        let fs = run("$ ls\ndir a\n0 b\n$ cd c").unwrap();
+        let dirs = fs.all_dirs().map(|d| fs.path(d)).collect::<Vec<_>>();
+        assert_eq!(dirs, vec!["/", "/a", "/c"]);
+        assert_eq!(fs.total_size(VirtualFs::ROOT), 0);
+
+        assert!(run("$ ls\n5 a\n$ cd a").is_err());
+        for entry in ["dir x/y", "5 ../../escape", "dir ..", "5 .", "dir /"] {
+            let err = run(&format!("$ ls\n{}", entry)).unwrap_err();
+            assert!(err.to_string().starts_with("line 2: "), "{}", err);
+        }
+        assert!(run("$ ls\n5 a\ndir a").is_err());
+        assert!(run("$ ls\ndir a\n5 a").is_err());
+    }
+
+    #[test]
+    fn test_incremental_sizes() {
+        let mut fs = run(include_str!("../../data/day07/example.txt")).unwrap();
+        fs.cd("/a/e").unwrap();
+        fs.add_entry(Entry::File(16, "i".into())).unwrap();
+        fs.add_entry(Entry::File(100, "new".into())).unwrap();
+        let incremental = fs.nodes.iter().map(|n| n.total_size).collect::<Vec<_>>();
+
+        fs.compute_sizes();
+        let recomputed = fs.nodes.iter().map(|n| n.total_size).collect::<Vec<_>>();
+        assert_eq!(incremental, recomputed);
+        assert_eq!(fs.total_size(VirtualFs::ROOT), 48381165 - 584 + 16 + 100);
+    }
+
+    #[test]
+    fn test_extended_commands() {
+        let fs = run("$ mkdir /src/day-07\n$ cd src\n$ ls\n120 main_1.rs\ndir day-07\n$ touch day-07/notes.txt\n$ cat 30 day-07/notes.txt\n$ touch main_1.rs\n$ cat 5 /README")
+            .unwrap();
+        assert_eq!(fs.total_size(VirtualFs::ROOT), 155);
+        let dirs = fs.all_dirs().map(|d| fs.path(d)).collect::<Vec<_>>();
+        assert_eq!(dirs, vec!["/", "/src", "/src/day-07"]);
+
+        let mut fs = fs;
+        fs.rm("day-07", true).unwrap();
+        fs.rm("/README", false).unwrap();
+        fs.touch("/src/empty").unwrap();
+        assert_eq!(fs.total_size(VirtualFs::ROOT), 120);
+        let incremental = fs.all_dirs().map(|d| fs.total_size(d)).collect::<Vec<_>>();
+        fs.compute_sizes();
+        let recomputed = fs.all_dirs().map(|d| fs.total_size(d)).collect::<Vec<_>>();
+        assert_eq!(incremental, recomputed);
+        assert_eq!(recomputed, vec![120, 120]);
+
+        assert!(run("$ mkdir a\n$ rm a").is_err());
+        assert!(run("$ rm -r missing").is_err());
+        assert!(run("$ mkdir a/b\n$ cd a/b\n$ rm -rf /a").is_err());
+        assert!(run("$ touch f\n$ mkdir f/g").is_err());
+        assert!(run("$ cat 5 ..").is_err());
+    }
+
+    #[test]
+    fn test_reports() {
+        let fs = run(include_str!("../../data/day07/example.txt")).unwrap();
+        let tree = fs.render_tree(VirtualFs::ROOT);
+        assert_eq!(
+            tree.lines().take(5).collect::<Vec<_>>(),
+            vec![
+                "- / (dir, size=48381165)",
+                "  - a (dir, size=94853)",
+                "    - e (dir, size=584)",
+                "      - i (file, size=584)",
+                "    - f (file, size=29116)",
+            ]
+        );
+        assert_eq!(
+            fs.du(),
+            vec![
+                (48381165, "/".to_string()),
+                (24933642, "/d".to_string()),
+                (94853, "/a".to_string()),
+                (584, "/a/e".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_transcript() {
+        let example = include_str!("../../data/day07/example.txt");
+        assert_eq!(check_transcript(example).unwrap(), vec![]);
+
+        let transcript = "$ cd /\n$ ls\ndir a\n5 f\n$ cd a\n1 x\n$ cd ..\n$ cd ..\n$ ls\n6 f\n5 f\n$ cd b\n$ mkdir /c/d\n$ cd /c/d";
+        let warnings = check_transcript(transcript)
+            .unwrap()
+            .iter()
+            .map(|w| w.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            warnings,
+            vec![
+                "line 6: ls output without a preceding `ls`",
+                "line 8: `cd ..` past the root directory, staying at /",
+                "line 10: /f listed again with size 6 instead of 5",
+                "line 11: /f listed again with size 5 instead of 6",
+                "line 12: `cd` into /b, which was never listed",
+            ]
+        );
+        let warnings = check_transcript("$ ls\ndir a\n5 f\n$ cd a/../f/g\n$ ls\n1 x").unwrap();
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(
+            warnings[0].to_string(),
+            "line 4: /f is already listed as a file"
+        );
+        assert!(check_transcript("$ ls\nnot a line").is_err());
+    }
+
+    #[test]
+    fn test_export() {
+        let fs = run("$ ls\ndir a\n12 b\n$ cd a").unwrap();
+        assert_eq!(
+            fs.to_json(VirtualFs::ROOT),
+            r#"{
+  "path": "/",
+  "kind": "dir",
+  "size": 12,
+  "children": [
+    {
+      "path": "/a",
+      "kind": "dir",
+      "size": 0,
+      "children": []
+    },
+    {
+      "path": "/b",
+      "kind": "file",
+      "size": 12
+    }
+  ]
+}
+"#
+        );
+
+        // the sizes on disk have to add up to what `du` reports for every directory
+        fn apparent_size(path: &Path) -> u64 {
+            let meta = fs::metadata(path).unwrap();
+            if meta.is_file() {
+                return meta.len();
+            }
+            fs::read_dir(path)
+                .unwrap()
+                .map(|entry| apparent_size(&entry.unwrap().path()))
+                .sum()
+        }
+
+        let vfs = run(include_str!("../../data/day07/example.txt")).unwrap();
+        let target = std::env::temp_dir().join(format!("day07-export-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&target);
+        vfs.materialize(&target).unwrap();
+        for (size, path) in vfs.du() {
+            assert_eq!(apparent_size(&target.join(&path[1..])), size, "{}", path);
+        }
+        assert!(vfs.materialize(&target).is_err());
+        fs::remove_dir_all(&target).unwrap();
+
+        // names that would climb out of the target never make it into the tree
+        for name in ["..", "../../escape", "a/../../b"] {
+            for transcript in [
+                format!("$ ls\n1 ok\n5 {name}"),
+                format!("$ ls\ndir {name}"),
+                format!("$ mkdir {name}/x\n$ ls\n5 y"),
+            ] {
+                assert!(run(&transcript).is_err(), "{}", transcript);
+            }
+        }
+    }
+
+    #[test]
+    fn test_space_to_free() {
+        let fs = run(include_str!("../../data/day07/example.txt")).unwrap();
+        assert_eq!(
+            space_to_free(&fs, 70000000, 30000000).unwrap(),
+            Some(8381165)
+        );
+        assert_eq!(space_to_free(&fs, 100000000, 30000000).unwrap(), None);
+        assert!(space_to_free(&fs, 1000, 0).is_err());
+
+        // /d alone frees the most, /a and /a/e can't both go
+        let (size, dirs) = dirs_to_free(&fs, 8381165).unwrap().unwrap();
+        assert_eq!((size, fs.path(dirs[0])), (24933642, "/d".to_string()));
+        let (size, dirs) = dirs_to_free(&fs, 90000).unwrap().unwrap();
+        assert_eq!((size, dirs.len()), (94853, 1));
+
+        let fs = run("$ ls\ndir a\ndir b\ndir c\n$ cd a\n$ ls\n60 x\n$ cd /b\n$ ls\n50 y\n$ cd /c\n$ ls\n100 z")
+            .unwrap();
+        let (size, dirs) = dirs_to_free(&fs, 105).unwrap().unwrap();
+        let paths = dirs.iter().map(|&d| fs.path(d)).collect::<Vec<_>>();
+        assert_eq!(
+            (size, paths),
+            (110, vec!["/a".to_string(), "/b".to_string()])
+        );
+        assert_eq!(dirs_to_free(&fs, 1000).unwrap(), None);
+
+        // real disk sizes are refused up front instead of allocating a bit per byte
+        let fs =
+            run("$ ls\ndir a\ndir b\n$ cd a\n$ ls\n60000000000 x\n$ cd /b\n$ ls\n50000000000 y")
+                .unwrap();
+        assert!(dirs_to_free(&fs, 40_000_000_000).is_err());
+        let fs =
+            run("$ ls\ndir a\ndir b\n$ cd a\n$ ls\n10000000000 x\n$ cd /b\n$ ls\n60 y").unwrap();
+        let (size, dirs) = dirs_to_free(&fs, 50).unwrap().unwrap();
+        assert_eq!((size, fs.path(dirs[0])), (60, "/b".to_string()));
+    }
+}
@@ -1,4 +1,9 @@
+use std::fs::File;
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
 use clap::Parser;
+use color_eyre::eyre::eyre;
 
 /// Argument template for Advent of Code
 #[derive(Parser, Debug)]
@@ -7,6 +12,31 @@ struct Args {
     /// Specify part to compute (1 or 2)
     #[arg(short, long, default_value_t = 1)]
     part: u8,
+
+    /// Draw the forest with hidden trees blanked out and the best tree highlighted
+    #[arg(long)]
+    render: bool,
+
+    /// Draw a heatmap of the scenic scores
+    #[arg(long)]
+    heatmap: bool,
+
+    /// Write the scenic score heatmap to FILE, as a PNG image if it ends in `.png` and as a
+    /// PPM image otherwise
+    #[arg(long, value_name = "FILE")]
+    image: Option<PathBuf>,
+
+    /// Pixels per tree in the image
+    #[arg(long, default_value_t = 4)]
+    scale: usize,
+
+    /// Explain what one tree sees in every direction (0-based row and column)
+    #[arg(long, num_args = 2, value_names = ["ROW", "COL"])]
+    explain: Option<Vec<usize>>,
+
+    /// Never use ANSI colours, even in a terminal
+    #[arg(long)]
+    no_color: bool,
 }
 
 fn get_input() -> &'static str {
@@ -14,115 +44,382 @@ fn get_input() -> &'static str {
     include_str!("../../data/day08/input.txt")
 }
 
-fn part1() -> color_eyre::Result<()> {
-    let lines: Vec<Vec<u8>> = get_input()
+/// Tree heights, one row per line. The forest doesn't have to be square, but rows must match.
+fn parse_forest(input: &str) -> color_eyre::Result<Vec<Vec<u8>>> {
+    let forest = input
         .lines()
-        .map(|line| {
+        .enumerate()
+        .map(|(i, line)| {
             line.bytes()
-                .filter(|b| b.is_ascii_digit())
-                .map(|b| b - b'0')
-                .collect()
+                .map(|b| match b {
+                    b'0'..=b'9' => Ok(b - b'0'),
+                    _ => Err(eyre!("row {}: {:?} isn't a height", i + 1, b as char)),
+                })
+                .collect::<color_eyre::Result<Vec<_>>>()
         })
-        .collect::<Vec<_>>();
-    dbg!(&lines);
-    let width = lines.get(0).unwrap().len();
-    let height = lines.len();
-    dbg!((width, height));
+        .collect::<color_eyre::Result<Vec<_>>>()?;
+    let cols = forest.first().map_or(0, |row| row.len());
+    if let Some(i) = forest.iter().position(|row| row.len() != cols) {
+        return Err(eyre!(
+            "row {} has {} trees, the first one has {}",
+            i + 1,
+            forest[i].len(),
+            cols
+        ));
+    }
+    Ok(forest)
+}
 
-    let mut visible_trees = lines
-        .iter()
-        .map(|line| line.iter().map(|_| false).collect::<Vec<_>>())
-        .collect::<Vec<_>>();
+/// What every tree of the forest sees.
+struct View {
+    /// Whether the tree can be seen from outside the forest.
+    visible: Vec<Vec<bool>>,
+    /// Product of the viewing distances in the four directions.
+    scores: Vec<Vec<usize>>,
+}
 
-    for i in 0..width {
-        let mut max: Option<u8> = None;
-        for j in 0..height {
-            let n = lines[i][j];
-            if max.map_or_else(|| true, |max| n > max) {
-                visible_trees[i][j] = true;
-                max = Some(n);
+/// Walks one line of trees from its first to its last position, marking the trees visible from
+/// the start of the line and multiplying each score by how far the tree sees towards the start.
+///
+/// `stack` holds the positions of the trees not yet hidden by a taller one after them, so
+/// their heights never increase and each tree is pushed and popped once.
+fn sweep(forest: &[Vec<u8>], line: &[(usize, usize)], view: &mut View, stack: &mut Vec<usize>) {
+    stack.clear();
+    for (i, &(row, col)) in line.iter().enumerate() {
+        let height = forest[row][col];
+        while stack
+            .last()
+            .is_some_and(|&j| forest[line[j].0][line[j].1] < height)
+        {
+            stack.pop();
+        }
+        match stack.last() {
+            Some(&blocker) => view.scores[row][col] *= i - blocker,
+            None => {
+                view.visible[row][col] = true;
+                view.scores[row][col] *= i;
             }
         }
+        stack.push(i);
+    }
+}
+
+fn view(forest: &[Vec<u8>]) -> View {
+    let rows = forest.len();
+    let cols = forest.first().map_or(0, |row| row.len());
+    let mut view = View {
+        visible: vec![vec![false; cols]; rows],
+        scores: vec![vec![1; cols]; rows],
+    };
+
+    let mut lines = vec![];
+    lines.extend((0..rows).map(|r| (0..cols).map(|c| (r, c)).collect::<Vec<_>>()));
+    lines.extend((0..cols).map(|c| (0..rows).map(|r| (r, c)).collect::<Vec<_>>()));
+    let mut stack = vec![];
+    for mut line in lines {
+        sweep(forest, &line, &mut view, &mut stack);
+        line.reverse();
+        sweep(forest, &line, &mut view, &mut stack);
+    }
+    view
+}
 
-        let mut max: Option<u8> = None;
-        for j in (0..height).rev() {
-            let n = lines[i][j];
-            if max.map_or_else(|| true, |max| n > max) {
-                visible_trees[i][j] = true;
-                max = Some(n);
+/// Position of the tree with the highest scenic score, the first one if there's a tie.
+fn best_tree(view: &View) -> Option<(usize, usize)> {
+    let mut best = None;
+    for (r, row) in view.scores.iter().enumerate() {
+        for (c, &score) in row.iter().enumerate() {
+            if best.is_none_or(|(_, s)| score > s) {
+                best = Some(((r, c), score));
             }
         }
     }
+    best.map(|(pos, _)| pos)
+}
 
-    for j in 0..height {
-        let mut max: Option<u8> = None;
-        for i in 0..width {
-            let n = lines[i][j];
-            if max.map_or_else(|| true, |max| n > max) {
-                visible_trees[i][j] = true;
-                max = Some(n);
+/// The forest with visible trees shown by height and hidden ones as `.`. The best tree is
+/// drawn inverted with colours, and as `@` without, like in the heatmap.
+fn render_visibility(forest: &[Vec<u8>], view: &View, color: bool) -> String {
+    let best = best_tree(view);
+    let mut out = String::new();
+    for (r, row) in forest.iter().enumerate() {
+        for (c, &height) in row.iter().enumerate() {
+            let ch = match view.visible[r][c] {
+                true => (b'0' + height) as char,
+                false => '.',
+            };
+            match (best == Some((r, c)), color) {
+                (true, true) => out.push_str(&format!("\x1b[1;7m{}\x1b[0m", ch)),
+                (true, false) => out.push('@'),
+                (false, true) if view.visible[r][c] => {
+                    out.push_str(&format!("\x1b[32m{}\x1b[0m", ch))
+                }
+                _ => out.push(ch),
             }
         }
+        out.push('\n');
+    }
+    out
+}
 
-        let mut max: Option<u8> = None;
-        for i in (0..width).rev() {
-            let n = lines[i][j];
-            if max.map_or_else(|| true, |max| n > max) {
-                visible_trees[i][j] = true;
-                max = Some(n);
+/// Where a score falls between 0 and the best score, on a log scale so that the many small
+/// scores don't all look the same.
+fn heat_level(score: usize, max: usize) -> f64 {
+    match max {
+        0 => 0.0,
+        _ => (score as f64).ln_1p() / (max as f64).ln_1p(),
+    }
+}
+
+/// Colour of a heat level, from dark blue through teal and yellow to red.
+fn heat_color(level: f64) -> (u8, u8, u8) {
+    const STOPS: [(f64, f64, f64); 4] = [
+        (20.0, 20.0, 80.0),
+        (0.0, 160.0, 160.0),
+        (250.0, 220.0, 40.0),
+        (230.0, 40.0, 30.0),
+    ];
+    let pos = level.clamp(0.0, 1.0) * (STOPS.len() - 1) as f64;
+    let i = (pos as usize).min(STOPS.len() - 2);
+    let t = pos - i as f64;
+    let (a, b) = (STOPS[i], STOPS[i + 1]);
+    let mix = |x: f64, y: f64| (x + (y - x) * t).round() as u8;
+    (mix(a.0, b.0), mix(a.1, b.1), mix(a.2, b.2))
+}
+
+/// Colour used for the best tree, which isn't on the heat scale.
+const BEST_COLOR: (u8, u8, u8) = (255, 0, 255);
+
+/// The scenic scores as coloured cells, or as characters from light to dense without colours.
+/// The best tree is `@` either way.
+fn render_heatmap(view: &View, color: bool) -> String {
+    const SHADES: &[u8] = b" .:-=+*#%";
+    let best = best_tree(view);
+    let max = view.scores.iter().flatten().copied().max().unwrap_or(0);
+    let mut out = String::new();
+    for (r, row) in view.scores.iter().enumerate() {
+        for (c, &score) in row.iter().enumerate() {
+            let level = heat_level(score, max);
+            let is_best = best == Some((r, c));
+            if color {
+                let (red, green, blue) = if is_best {
+                    BEST_COLOR
+                } else {
+                    heat_color(level)
+                };
+                let ch = if is_best { '@' } else { ' ' };
+                out.push_str(&format!(
+                    "\x1b[48;2;{};{};{}m{}\x1b[0m",
+                    red, green, blue, ch
+                ));
+            } else if is_best {
+                out.push('@');
+            } else {
+                let shade = (level * (SHADES.len() - 1) as f64).round() as usize;
+                out.push(SHADES[shade] as char);
             }
         }
+        out.push('\n');
+    }
+    out
+}
+
+/// The heatmap as rows of RGB pixels, `scale` pixels per tree.
+fn heatmap_pixels(view: &View, scale: usize) -> Vec<Vec<u8>> {
+    let best = best_tree(view);
+    let max = view.scores.iter().flatten().copied().max().unwrap_or(0);
+    let mut pixels = vec![];
+    for (r, row) in view.scores.iter().enumerate() {
+        let line = row
+            .iter()
+            .enumerate()
+            .flat_map(|(c, &score)| {
+                let (red, green, blue) = match best == Some((r, c)) {
+                    true => BEST_COLOR,
+                    false => heat_color(heat_level(score, max)),
+                };
+                [red, green, blue].repeat(scale)
+            })
+            .collect::<Vec<_>>();
+        pixels.extend(std::iter::repeat_n(line, scale));
     }
+    pixels
+}
 
-    dbg!(visible_trees.iter().flatten().filter(|b| **b).count());
+/// Writes rows of RGB pixels as a binary PPM image.
+fn write_ppm(pixels: &[Vec<u8>], mut out: impl Write) -> io::Result<()> {
+    let width = pixels.first().map_or(0, |row| row.len() / 3);
+    writeln!(out, "P6\n{} {}\n255", width, pixels.len())?;
+    for row in pixels {
+        out.write_all(row)?;
+    }
     Ok(())
 }
 
-fn count_view<'a>(values: impl Iterator<Item = &'a u8> + Clone, height: u8) -> usize {
-    let length = values.clone().count();
-
-    let count = values
-        .clone()
-        .position(|&n| n >= height)
-        .map(|i| i + 1)
-        .unwrap_or(length);
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            crc = (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg());
+        }
+    }
+    !crc
+}
 
-    count
+fn adler32(bytes: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in bytes {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    b << 16 | a
 }
 
-fn score_scenery(lines: &Vec<Vec<u8>>, i: usize, j: usize) -> usize {
-    let z_height = lines[i][j];
+/// Writes rows of RGB pixels as a PNG image.
+///
+/// The image data goes into stored deflate blocks, which any decoder reads without us having to
+/// compress anything. The files are about as big as PPM ones.
+fn write_png(pixels: &[Vec<u8>], mut out: impl Write) -> io::Result<()> {
+    fn chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+        let length = u32::try_from(data.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "image too big for PNG"))?;
+        out.write_all(&length.to_be_bytes())?;
+        let body = [&kind[..], data].concat();
+        out.write_all(&body)?;
+        out.write_all(&crc32(&body).to_be_bytes())
+    }
+
+    let width = pixels.first().map_or(0, |row| row.len() / 3) as u32;
+    let mut header = vec![];
+    header.extend(width.to_be_bytes());
+    header.extend((pixels.len() as u32).to_be_bytes());
+    // 8 bits per channel, RGB, no interlacing
+    header.extend([8, 2, 0, 0, 0]);
 
-    let north = count_view(lines[..i].iter().map(|line| &line[j]).rev(), z_height);
-    let south = count_view(lines[i + 1..].iter().map(|line| &line[j]), z_height);
-    let west = count_view(lines[i][..j].iter().rev(), z_height);
-    let east = count_view(lines[i][j + 1..].iter(), z_height);
+    // every row starts with filter type 0, meaning the bytes are stored as they are
+    let raw = pixels
+        .iter()
+        .flat_map(|row| std::iter::once(0).chain(row.iter().copied()))
+        .collect::<Vec<_>>();
+    let mut zlib = vec![0x78, 0x01];
+    let blocks = raw.chunks(u16::MAX as usize).collect::<Vec<_>>();
+    for (i, block) in blocks.iter().enumerate() {
+        zlib.push((i + 1 == blocks.len()) as u8);
+        zlib.extend((block.len() as u16).to_le_bytes());
+        zlib.extend((!(block.len() as u16)).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    if blocks.is_empty() {
+        zlib.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    zlib.extend(adler32(&raw).to_be_bytes());
 
-    north * south * west * east
+    out.write_all(b"\x89PNG\r\n\x1a\n")?;
+    chunk(&mut out, b"IHDR", &header)?;
+    chunk(&mut out, b"IDAT", &zlib)?;
+    chunk(&mut out, b"IEND", &[])
 }
 
-fn part2() -> color_eyre::Result<()> {
-    let lines: Vec<Vec<u8>> = get_input()
-        .lines()
-        .map(|line| {
-            line.bytes()
-                .filter(|b| b.is_ascii_digit())
-                .map(|b| b - b'0')
-                .collect()
-        })
-        .collect::<Vec<_>>();
-    dbg!(&lines);
-    let width = lines.get(0).unwrap().len();
-    let height = lines.len();
-    dbg!((width, height));
+fn save_image(view: &View, scale: usize, path: &Path) -> color_eyre::Result<()> {
+    if scale == 0 {
+        return Err(eyre!("the scale must be at least 1"));
+    }
+    let pixels = heatmap_pixels(view, scale);
+    let mut out = BufWriter::new(File::create(path)?);
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("png") => write_png(&pixels, &mut out)?,
+        _ => write_ppm(&pixels, &mut out)?,
+    }
+    out.flush()?;
+    Ok(())
+}
 
-    let mut max: Option<usize> = None;
+/// For every direction, how far the tree at `(row, col)` sees and what stops its view.
+fn explain(forest: &[Vec<u8>], row: usize, col: usize) -> color_eyre::Result<String> {
+    let height = *forest
+        .get(row)
+        .and_then(|r| r.get(col))
+        .ok_or_else(|| eyre!("there's no tree at row {}, column {}", row, col))?;
+    let rows = forest.len();
+    let cols = forest[0].len();
+    let rays: [(&str, Vec<(usize, usize)>); 4] = [
+        ("up", (0..row).rev().map(|r| (r, col)).collect()),
+        ("down", (row + 1..rows).map(|r| (r, col)).collect()),
+        ("left", (0..col).rev().map(|c| (row, c)).collect()),
+        ("right", (col + 1..cols).map(|c| (row, c)).collect()),
+    ];
 
-    for i in 0..width {
-        for j in 0..height {
-            max = max.max(Some(score_scenery(&lines, i, j)));
-        }
+    let mut out = format!(
+        "tree at row {}, column {} has height {}\n",
+        row, col, height
+    );
+    let mut score = 1;
+    for (name, ray) in rays {
+        let blocker = ray.iter().position(|&(r, c)| forest[r][c] >= height);
+        let distance = blocker.map_or(ray.len(), |i| i + 1);
+        score *= distance;
+        let reason = match blocker {
+            Some(i) => {
+                let (r, c) = ray[i];
+                format!(
+                    "hidden by the tree at row {}, column {} (height {})",
+                    r, c, forest[r][c]
+                )
+            }
+            None => "visible from the edge".to_string(),
+        };
+        out.push_str(&format!(
+            "  {:<5} sees {} trees, {}\n",
+            name, distance, reason
+        ));
+    }
+    out.push_str(&format!("  scenic score {}\n", score));
+    Ok(out)
+}
+
+fn visualize(args: &Args) -> color_eyre::Result<()> {
+    let forest = parse_forest(get_input())?;
+    let view = view(&forest);
+    let color = !args.no_color && io::stdout().is_terminal();
+
+    if args.render {
+        print!("{}", render_visibility(&forest, &view, color));
     }
+    if args.heatmap {
+        print!("{}", render_heatmap(&view, color));
+    }
+    if let Some(path) = &args.image {
+        save_image(&view, args.scale, path)?;
+        println!("wrote {}", path.display());
+    }
+    if let Some((row, col)) = best_tree(&view) {
+        println!(
+            "best tree: row {}, column {}, scenic score {}",
+            row, col, view.scores[row][col]
+        );
+    }
+    if let Some(&[row, col]) = args.explain.as_deref() {
+        print!("{}", explain(&forest, row, col)?);
+    }
+    Ok(())
+}
+
+fn part1() -> color_eyre::Result<()> {
+    let forest = parse_forest(get_input())?;
+    dbg!((forest.len(), forest.first().map_or(0, |row| row.len())));
+
+    let view = view(&forest);
+    dbg!(view.visible.iter().flatten().filter(|b| **b).count());
+    Ok(())
+}
+
+fn part2() -> color_eyre::Result<()> {
+    let forest = parse_forest(get_input())?;
+    dbg!((forest.len(), forest.first().map_or(0, |row| row.len())));
+
+    let view = view(&forest);
+    let max = view.scores.iter().flatten().max();
     dbg!(max);
     Ok(())
 }
@@ -130,12 +427,124 @@ fn part2() -> color_eyre::Result<()> {
 fn main() -> color_eyre::Result<()> {
     color_eyre::install()?;
 
-    let part = Args::parse().part;
+    let args = Args::parse();
+    if args.render || args.heatmap || args.image.is_some() || args.explain.is_some() {
+        return visualize(&args);
+    }
+
+    let part = args.part;
     println!("Running for part{}", part);
-    match Args::parse().part {
+    match part {
         1 => part1()?,
         2 => part2()?,
         _ => panic!("part argument not recognized"),
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use aoc2022::rng::Rng;
+
+    use super::*;
+
+    fn count_view<'a>(values: impl Iterator<Item = &'a u8> + Clone, height: u8) -> usize {
+        let length = values.clone().count();
+        values
+            .clone()
+            .position(|&n| n >= height)
+            .map_or(length, |i| i + 1)
+    }
+
+    /// Looks along the four rays of every tree, the way part 2 used to.
+    fn naive_view(forest: &[Vec<u8>]) -> View {
+        let rows = forest.len();
+        let cols = forest.first().map_or(0, |row| row.len());
+        let mut view = View {
+            visible: vec![vec![false; cols]; rows],
+            scores: vec![vec![0; cols]; rows],
+        };
+        for i in 0..rows {
+            for j in 0..cols {
+                let h = forest[i][j];
+                let column = forest.iter().map(|row| &row[j]).collect::<Vec<_>>();
+                let rays: [Vec<&u8>; 4] = [
+                    column[..i].iter().rev().copied().collect(),
+                    column[i + 1..].to_vec(),
+                    forest[i][..j].iter().rev().collect(),
+                    forest[i][j + 1..].iter().collect(),
+                ];
+                view.visible[i][j] = rays.iter().any(|ray| ray.iter().all(|&&n| n < h));
+                view.scores[i][j] = rays
+                    .iter()
+                    .map(|ray| count_view(ray.iter().copied(), h))
+                    .product();
+            }
+        }
+        view
+    }
+
+    #[test]
+    fn test_view() {
+        let forest = parse_forest(include_str!("../../data/day08/example.txt")).unwrap();
+        let view = view(&forest);
+        assert_eq!(view.visible.iter().flatten().filter(|b| **b).count(), 21);
+        assert_eq!(view.scores.iter().flatten().max(), Some(&8));
+        assert_eq!(view.scores[3][2], 8);
+
+        assert!(parse_forest("123\n45").is_err());
+        assert!(parse_forest("1a3").is_err());
+    }
+
+    #[test]
+    fn test_rendering() {
+        let forest = parse_forest(include_str!("../../data/day08/example.txt")).unwrap();
+        let view = view(&forest);
+        assert_eq!(best_tree(&view), Some((3, 2)));
+        assert_eq!(
+            render_visibility(&forest, &view, false),
+            "30373\n255.2\n65.32\n3.@.9\n35390\n"
+        );
+        let heatmap = render_heatmap(&view, false);
+        assert_eq!(heatmap.lines().nth(3).unwrap().chars().nth(2), Some('@'));
+        assert_eq!(heatmap.lines().next(), Some("     "));
+
+        let pixels = heatmap_pixels(&view, 2);
+        assert_eq!((pixels.len(), pixels[0].len()), (10, 10 * 3));
+        let mut ppm = vec![];
+        write_ppm(&pixels, &mut ppm).unwrap();
+        let header = b"P6\n10 10\n255\n";
+        assert!(ppm.starts_with(header));
+        assert_eq!(ppm.len(), header.len() + 10 * 10 * 3);
+
+        let mut png = vec![];
+        write_png(&pixels, &mut png).unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x0a\0\0\0\x0a"));
+        // the IDAT holds one stored block: zlib header, block header, 10 rows of 1 + 30 bytes
+        let idat = 8 + 25;
+        assert_eq!(&png[idat..idat + 4], &(2 + 5 + 310 + 4u32).to_be_bytes());
+        assert!(png.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+
+        assert_eq!(heat_color(0.0), (20, 20, 80));
+        assert_eq!(heat_color(1.0), (230, 40, 30));
+        assert!(explain(&forest, 3, 2)
+            .unwrap()
+            .ends_with("scenic score 8\n"));
+        assert!(explain(&forest, 5, 0).is_err());
+    }
+
+    #[test]
+    fn test_view_matches_naive() {
+        let mut rng = Rng::new(8);
+        for _ in 0..200 {
+            let (rows, cols) = (rng.below(8) as usize + 1, rng.below(8) as usize + 1);
+            let forest = (0..rows)
+                .map(|_| (0..cols).map(|_| rng.below(5) as u8).collect())
+                .collect::<Vec<Vec<_>>>();
+            let (fast, naive) = (view(&forest), naive_view(&forest));
+            assert_eq!(fast.visible, naive.visible, "{:?}", forest);
+            assert_eq!(fast.scores, naive.scores, "{:?}", forest);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+
+/// Integer-like bounds, for the operations that need to count or step between values.
+pub trait Discrete: Copy + Ord {
+    /// The next value, or `None` at the top of the type's range.
+    fn succ(self) -> Option<Self>;
+
+    /// The previous value, or `None` at the bottom of the type's range.
+    fn pred(self) -> Option<Self>;
+
+    /// Number of values in `start..=end`.
+    fn count(start: Self, end: Self) -> u64;
+}
+
+macro_rules! impl_discrete {
+    ($($t:ty),*) => {
+        $(impl Discrete for $t {
+            fn succ(self) -> Option<Self> {
+                self.checked_add(1)
+            }
+
+            fn pred(self) -> Option<Self> {
+                self.checked_sub(1)
+            }
+
+            fn count(start: Self, end: Self) -> u64 {
+                (end as i128 - start as i128 + 1) as u64
+            }
+        })*
+    };
+}
+
+impl_discrete!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// A non-empty closed interval `start..=end`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Interval<T> {
+    start: T,
+    end: T,
+}
+
+impl<T: Copy + Ord> Interval<T> {
+    /// Returns `None` if `start > end`.
+    pub fn new(start: T, end: T) -> Option<Self> {
+        (start <= end).then_some(Interval { start, end })
+    }
+
+    pub fn start(&self) -> T {
+        self.start
+    }
+
+    pub fn end(&self) -> T {
+        self.end
+    }
+
+    pub fn contains_point(&self, x: T) -> bool {
+        self.start <= x && x <= self.end
+    }
+
+    /// Whether `other` lies entirely inside `self`.
+    pub fn contains(&self, other: &Interval<T>) -> bool {
+        self.start <= other.start && other.end <= self.end
+    }
+
+    pub fn overlaps(&self, other: &Interval<T>) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+
+    pub fn intersection(&self, other: &Interval<T>) -> Option<Interval<T>> {
+        Interval::new(self.start.max(other.start), self.end.min(other.end))
+    }
+}
+
+impl<T: Discrete> Interval<T> {
+    pub fn len(&self) -> u64 {
+        T::count(self.start, self.end)
+    }
+
+    /// Always false, intervals hold at least one value.
+    pub fn is_empty(&self) -> bool {
+        false
+    }
+
+    /// Whether the two intervals overlap or sit next to each other.
+    pub fn touches(&self, other: &Interval<T>) -> bool {
+        let (first, second) = if self.start <= other.start {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        first.end.succ().is_none_or(|next| second.start <= next)
+    }
+
+    /// The interval covering both, if there is no gap between them.
+    pub fn union(&self, other: &Interval<T>) -> Option<Interval<T>> {
+        self.touches(other).then(|| Interval {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        })
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Interval<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+/// A set of values stored as sorted, disjoint, non-adjacent intervals.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IntervalSet<T> {
+    intervals: Vec<Interval<T>>,
+}
+
+impl<T: Discrete> IntervalSet<T> {
+    pub fn new() -> Self {
+        IntervalSet { intervals: vec![] }
+    }
+
+    pub fn insert(&mut self, interval: Interval<T>) {
+        // first interval that could merge with the new one
+        let lo = self
+            .intervals
+            .partition_point(|i| i.end < interval.start && !i.touches(&interval));
+        let mut merged = interval;
+        let mut hi = lo;
+        while let Some(next) = self.intervals.get(hi).and_then(|i| merged.union(i)) {
+            merged = next;
+            hi += 1;
+        }
+        self.intervals.splice(lo..hi, [merged]);
+    }
+
+    pub fn contains_point(&self, x: T) -> bool {
+        let i = self.intervals.partition_point(|i| i.end < x);
+        self.intervals
+            .get(i)
+            .is_some_and(|interval| interval.contains_point(x))
+    }
+
+    /// Number of values covered by the set.
+    pub fn len(&self) -> u64 {
+        self.intervals.iter().map(|i| i.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.intervals.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Interval<T>> {
+        self.intervals.iter()
+    }
+}
+
+impl<T: Discrete> FromIterator<Interval<T>> for IntervalSet<T> {
+    fn from_iter<I: IntoIterator<Item = Interval<T>>>(iter: I) -> Self {
+        let mut sorted = iter.into_iter().collect::<Vec<_>>();
+        sorted.sort_unstable();
+
+        let mut intervals: Vec<Interval<T>> = vec![];
+        for interval in sorted {
+            match intervals.last_mut() {
+                Some(last) if last.touches(&interval) => {
+                    last.end = last.end.max(interval.end);
+                }
+                _ => intervals.push(interval),
+            }
+        }
+        IntervalSet { intervals }
+    }
+}
+
+/// Sweeps over `intervals` and splits the span they cover into maximal runs of constant depth,
+/// the number of intervals covering each value. Gaps between intervals are runs of depth 0.
+pub fn depth_runs<T: Discrete>(
+    intervals: impl IntoIterator<Item = Interval<T>>,
+) -> Vec<(Interval<T>, usize)> {
+    let mut events = vec![];
+    let mut last = None;
+    for interval in intervals {
+        events.push((interval.start, 1isize));
+        if let Some(after) = interval.end.succ() {
+            events.push((after, -1));
+        }
+        last = last.max(Some(interval.end));
+    }
+    events.sort_unstable();
+
+    let mut runs = vec![];
+    let mut depth = 0isize;
+    let mut run_start = None;
+    for (i, &(pos, delta)) in events.iter().enumerate() {
+        depth += delta;
+        if events.get(i + 1).is_some_and(|&(next, _)| next == pos) {
+            continue;
+        }
+        if let Some((start, d)) = run_start {
+            if let Some(end) = pos.pred() {
+                runs.extend(Interval::new(start, end).map(|run| (run, d as usize)));
+            }
+        }
+        run_start = Some((pos, depth));
+    }
+    // runs reaching the top of the type's range never get a closing event
+    if let (Some((start, d)), Some(end)) = (run_start, last) {
+        if d > 0 {
+            runs.extend(Interval::new(start, end).map(|run| (run, d as usize)));
+        }
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn random_interval(rng: &mut Rng) -> Interval<u32> {
+        let (a, b) = (rng.below(40) as u32, rng.below(40) as u32);
+        Interval::new(a.min(b), a.max(b)).unwrap()
+    }
+
+    fn points(interval: &Interval<u32>) -> Vec<u32> {
+        (interval.start()..=interval.end()).collect()
+    }
+
+    #[test]
+    fn test_interval() {
+        let a = Interval::new(2, 8).unwrap();
+        let b = Interval::new(3, 7).unwrap();
+        assert!(a.contains(&b));
+        assert!(!b.contains(&a));
+        assert!(a.overlaps(&b));
+        assert_eq!(a.len(), 7);
+        assert_eq!(Interval::new(5, 4), None);
+        assert_eq!(
+            Interval::new(1, 3)
+                .unwrap()
+                .union(&Interval::new(4, 6).unwrap()),
+            Interval::new(1, 6)
+        );
+        assert_eq!(
+            Interval::new(1, 3)
+                .unwrap()
+                .union(&Interval::new(5, 6).unwrap()),
+            None
+        );
+        assert!(Interval::new(0, u8::MAX)
+            .unwrap()
+            .touches(&Interval::new(u8::MAX, u8::MAX).unwrap()));
+    }
+
+    #[test]
+    fn test_depth_runs() {
+        let runs = depth_runs([
+            Interval::new(2, 4).unwrap(),
+            Interval::new(6, 8).unwrap(),
+            Interval::new(3, 7).unwrap(),
+        ]);
+        assert_eq!(
+            runs.iter()
+                .map(|(i, d)| (i.start(), i.end(), *d))
+                .collect::<Vec<_>>(),
+            vec![(2, 2, 1), (3, 4, 2), (5, 5, 1), (6, 7, 2), (8, 8, 1)]
+        );
+
+        let runs = depth_runs([
+            Interval::new(1, 2).unwrap(),
+            Interval::new(5, u8::MAX).unwrap(),
+        ]);
+        assert_eq!(
+            runs.iter()
+                .map(|(i, d)| (i.start(), i.end(), *d))
+                .collect::<Vec<_>>(),
+            vec![(1, 2, 1), (3, 4, 0), (5, u8::MAX, 1)]
+        );
+        assert!(depth_runs::<u32>([]).is_empty());
+    }
+
+    #[test]
+    fn prop_interval_ops_match_point_sets() {
+        let mut rng = Rng::new(30);
+        for _ in 0..2000 {
+            let (a, b) = (random_interval(&mut rng), random_interval(&mut rng));
+            let (pa, pb) = (points(&a), points(&b));
+            let common = pa.iter().filter(|x| pb.contains(x)).count();
+
+            assert_eq!(a.len() as usize, pa.len());
+            assert_eq!(a.contains(&b), pb.iter().all(|x| pa.contains(x)));
+            assert_eq!(a.overlaps(&b), common > 0);
+            assert_eq!(a.intersection(&b).map_or(0, |i| i.len()) as usize, common);
+            assert_eq!(a.overlaps(&b), b.overlaps(&a));
+            if let Some(u) = a.union(&b) {
+                assert_eq!(u.len() as usize, pa.len() + pb.len() - common);
+            }
+        }
+    }
+
+    #[test]
+    fn prop_interval_set_is_normalized() {
+        let mut rng = Rng::new(31);
+        for _ in 0..500 {
+            let intervals = (0..rng.below(8))
+                .map(|_| random_interval(&mut rng))
+                .collect::<Vec<_>>();
+            let covered = |x: u32| intervals.iter().any(|i| i.contains_point(x));
+
+            let collected = intervals.iter().copied().collect::<IntervalSet<_>>();
+            let mut inserted = IntervalSet::new();
+            intervals.iter().for_each(|&i| inserted.insert(i));
+            assert_eq!(collected, inserted);
+
+            for x in 0..45 {
+                assert_eq!(collected.contains_point(x), covered(x));
+            }
+            assert_eq!(
+                collected.len() as usize,
+                (0..45).filter(|&x| covered(x)).count()
+            );
+            for pair in collected.iter().collect::<Vec<_>>().windows(2) {
+                assert!(!pair[0].touches(pair[1]));
+                assert!(pair[0].end() < pair[1].start());
+            }
+
+            for (run, depth) in depth_runs(intervals.iter().copied()) {
+                for x in points(&run) {
+                    assert_eq!(
+                        intervals.iter().filter(|i| i.contains_point(x)).count(),
+                        depth
+                    );
+                }
+            }
+        }
+    }
+}
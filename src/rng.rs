@@ -0,0 +1,42 @@
+//! A seeded random number generator for simulations and randomized tests that have to be
+//! reproducible, without pulling in a crate for it.
+
+/// SplitMix64, small and good enough for anything that isn't cryptography.
+#[derive(Debug, Clone)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number below `n`, which has to be at least 1.
+    pub fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rng() {
+        let draws = |seed| {
+            let mut rng = Rng::new(seed);
+            (0..100).map(|_| rng.below(6)).collect::<Vec<_>>()
+        };
+        assert_eq!(draws(1), draws(1));
+        assert_ne!(draws(1), draws(2));
+        assert!(draws(3).iter().all(|&n| n < 6));
+        assert!((0..6).all(|n| draws(4).contains(&n)));
+    }
+}